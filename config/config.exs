@@ -1,4 +1 @@
 use Mix.Config
-
-config :tantex,
-  encoder: Tantex.JasonEncoder
@@ -3,11 +3,11 @@ defmodule Tantex do
     Index
   }
 
-  def open(path, fields) when is_binary(path) and is_list(fields) do
+  def open(path, fields, opts \\ []) when is_binary(path) and is_list(fields) do
     with(
       {:ok, index} <- Index.new(fields),
       :ok <- Index.finalize_schema(index),
-      {:ok, index} <- Index.open_index(index, path)
+      {:ok, index} <- Index.open_index(index, path, opts)
     ) do
       {:ok, index}
     else
@@ -16,8 +16,32 @@ defmodule Tantex do
     end
   end
 
+  defdelegate find_many(index, fields, search_term, limit, opts), to: Index
   defdelegate find_many(index, fields, search_term, limit), to: Index
+  defdelegate find_page(index, fields, search_term, limit, opts), to: Index
+  defdelegate find_page(index, fields, search_term, limit), to: Index
+  defdelegate stream(index, fields, search_term, pid, opts), to: Index
+  defdelegate stream(index, fields, search_term, pid), to: Index
+  defdelegate ack_stream(stream), to: Index
+  defdelegate cancel_stream(stream), to: Index
+  defdelegate pin_snapshot(index), to: Index
+  defdelegate release_snapshot(snapshot), to: Index
+  defdelegate find_one(index, field, search_term, opts), to: Index
   defdelegate find_one(index, field, search_term), to: Index
+  defdelegate find_all(index, field, search_term, opts), to: Index
+  defdelegate find_all(index, field, search_term), to: Index
+  defdelegate find_by_keys(index, field, keys, opts), to: Index
+  defdelegate find_by_keys(index, field, keys), to: Index
+  defdelegate find_by_regex(index, field, pattern, limit, opts), to: Index
+  defdelegate find_by_regex(index, field, pattern, limit), to: Index
+  defdelegate explain(index, fields, search_term, key, opts), to: Index
+  defdelegate explain(index, fields, search_term, key), to: Index
+  defdelegate find_grouped(index, fields, search_term, group_field, opts), to: Index
+  defdelegate find_grouped(index, fields, search_term, group_field), to: Index
+  defdelegate aggregate(index, fields, search_term, aggregations, opts), to: Index
+  defdelegate aggregate(index, fields, search_term, aggregations), to: Index
+  defdelegate parse_query(index, fields, search_term, opts), to: Index
+  defdelegate parse_query(index, fields, search_term), to: Index
   defdelegate insert_documents(index, documents, opts), to: Index
   defdelegate insert_documents(index, documents), to: Index
 end
@@ -16,13 +16,16 @@ defmodule Tantex.Field do
           kind: kind(),
           name: String.t(),
           fast: boolean(),
-          stored: boolean()
+          stored: boolean(),
+          multi: boolean()
         }
 
-  defstruct [:kind, :name, :fast, :stored]
+  defstruct [:kind, :name, :fast, :stored, multi: false]
 
   defguard is_kind(k) when k in @kinds
 
+  # `multi: true` declares a field that holds several values per document.
+  # It is written and returned as a list; every other field holds one value.
   @spec build(binary(), kind(), Keyword.t()) :: Field.t()
   def build(name, kind, opts \\ []) when is_kind(kind) and is_binary(name) and is_list(opts) do
     {stored, fast} = generate_stored_and_fast(kind, opts)
@@ -31,7 +34,8 @@ defmodule Tantex.Field do
       name: name,
       kind: kind,
       stored: stored,
-      fast: fast
+      fast: fast,
+      multi: Keyword.get(opts, :multi, false)
     }
   end
 
@@ -40,9 +44,10 @@ defmodule Tantex.Field do
         kind: kind,
         name: name,
         stored: stored,
-        fast: fast
+        fast: fast,
+        multi: multi
       }) do
-    {name, to_string(kind), stored, fast}
+    {name, to_string(kind), stored, fast, multi}
   end
 
   defp generate_stored_and_fast(k, opts) when k in [:i64, :u64] do
@@ -1,5 +1,5 @@
 defmodule Tantex.Index do
-  alias Tantex.{Encoder, Native, Index, Field}
+  alias Tantex.{Native, Index, Field}
 
   @type field_name :: atom() | binary() | Field.t()
   @type t :: %__MODULE__{
@@ -8,8 +8,36 @@ defmodule Tantex.Index do
           path: nil | String.t()
         }
 
+  @type explanation :: %{
+          description: String.t(),
+          matched: boolean(),
+          score: float(),
+          details: list(explanation())
+        }
+
+  @type stream :: {pos_integer(), reference()}
+
+  @type query_tree ::
+          {:boolean, list({:must | :should | :must_not, query_tree()})}
+          | {:term, String.t(), String.t() | integer()}
+          | {:phrase, String.t(), list(String.t())}
+          | {:range, String.t(), String.t()}
+          | {:boost, float(), query_tree()}
+          | :all
+          | {:other, String.t()}
+
   defstruct __ref__: nil, fields: [], path: nil
 
+  @regex_size_limit 1_000_000
+  @snippet_max_num_chars 150
+  @snippet_unsupported_options [:aggregations, :after, :snapshot]
+  @stream_chunk_size 500
+  @stream_timeout 60_000
+  @max_snapshots 8
+  @cardinality_precision 14
+  @percentiles [1, 5, 25, 50, 75, 95, 99]
+  @percentiles_compression 100
+
   def new(fields \\ []) do
     with(
       {:ok, ref} <- Native.new_schema_index(),
@@ -29,10 +57,11 @@ defmodule Tantex.Index do
           name: name,
           kind: kind,
           fast: fast,
-          stored: stored
+          stored: stored,
+          multi: multi
         } = field
       ) do
-    case Native.add_field(ref, to_string(name), to_string(kind), stored, fast) do
+    case Native.add_field(ref, to_string(name), to_string(kind), stored, fast, multi) do
       :ok ->
         {:ok, %Index{index | fields: [field | prev]}}
 
@@ -59,40 +88,305 @@ defmodule Tantex.Index do
 
   def insert_documents(%Index{__ref__: ref}, docs, opts \\ []) when is_list(docs) do
     heap_size = Keyword.get(opts, :heap_size, 50_000_000)
-    encoder = Encoder.get_encoder()
-    list_of_docs = Enum.map(docs, fn doc -> encoder.encode_map(doc) end)
-    Native.write_documents(ref, list_of_docs, heap_size)
+    Native.write_documents(ref, docs, heap_size)
   end
 
-  @spec find_many(Tantex.Index.t(), list(String.t()), String.t(), non_neg_integer()) ::
-          {:ok, list(map())} | Native.error()
-  def find_many(%Index{__ref__: ref}, fields, search_terms, limit) do
+  @spec find_many(
+          Tantex.Index.t(),
+          list(String.t()),
+          String.t(),
+          non_neg_integer(),
+          Keyword.t()
+        ) :: {:ok, list(map())} | {:ok, list(map()), map()} | Native.error()
+  def find_many(%Index{__ref__: ref}, fields, search_terms, limit, opts \\ []) do
     fields = Enum.map(fields, &string_field/1)
 
-    case Native.limit_search(ref, fields, search_terms, limit) do
-      {:ok, json_list} ->
-        encoder = Encoder.get_encoder()
-        {:ok, Enum.map(json_list, fn item -> encoder.decode_map(item) end)}
+    case Keyword.get(opts, :snippets) do
+      nil ->
+        {names, aggregations} = opts |> Keyword.get(:aggregations, []) |> Enum.unzip()
 
-      err ->
-        err
+        case limit_search(ref, fields, search_terms, limit, aggregations, opts) do
+          {:ok, docs, _, _} when names == [] -> {:ok, docs}
+          {:ok, docs, results, _} ->
+            {:ok, docs, decode_aggregations(names, aggregations, results)}
+
+          err -> err
+        end
+
+      snippet_fields when is_list(snippet_fields) ->
+        find_many_with_snippets(ref, fields, search_terms, limit, snippet_fields, opts)
+    end
+  end
+
+  # Returns one page of hits and the cursor to pass as `:after` for the next
+  # page, or `nil` once the last page has been reached. Takes the same options
+  # as `find_many/5` apart from `:snippets` and `:aggregations`.
+  #
+  # A cursor only resumes against the documents it was cut from: after a
+  # commit it fails with `{:error, {:invalid_cursor, _}}`. Page through a
+  # snapshot from `pin_snapshot/1` to keep paging across commits.
+  @spec find_page(
+          Tantex.Index.t(),
+          list(String.t()),
+          String.t(),
+          non_neg_integer(),
+          Keyword.t()
+        ) :: {:ok, list(map()), String.t() | nil} | Native.error()
+  def find_page(%Index{__ref__: ref}, fields, search_terms, limit, opts \\ []) do
+    fields = Enum.map(fields, &string_field/1)
+
+    case limit_search(ref, fields, search_terms, limit, [], opts) do
+      {:ok, docs, [], cursor} -> {:ok, docs, cursor}
+      err -> err
     end
   end
 
-  @spec find_one(Index.t(), field_name(), String.t()) :: {:ok, map} | Native.error()
-  def find_one(%Index{__ref__: ref}, field, term) when is_binary(term) or is_integer(term) do
-    case Native.find_one_by_term(ref, string_field(field), term) do
-      {:ok, json_doc} ->
-        {:ok, Encoder.get_encoder().decode_map(json_doc)}
+  defp limit_search(ref, fields, search_terms, limit, aggregations, opts) do
+    Native.limit_search(
+      ref,
+      fields,
+      search_terms,
+      limit,
+      return_fields(opts),
+      filters(opts),
+      parser_options(opts),
+      Enum.map(aggregations, &native_aggregation/1),
+      Keyword.get(opts, :after),
+      Keyword.get(opts, :snapshot)
+    )
+  end
+
+  # Snippets honor `:filters`, `:conjunction` and `:boosts`; any option the
+  # snippet search cannot apply is rejected rather than ignored.
+  defp find_many_with_snippets(ref, fields, search_terms, limit, snippet_fields, opts) do
+    case Enum.find(@snippet_unsupported_options, &Keyword.has_key?(opts, &1)) do
+      nil -> snippet_search(ref, fields, search_terms, limit, snippet_fields, opts)
+      option -> {:error, {:unsupported_option, "#{option} cannot be used with snippets"}}
+    end
+  end
+
+  defp snippet_search(ref, fields, search_terms, limit, snippet_fields, opts) do
+    snippet_fields = Enum.map(snippet_fields, &string_field/1)
+    pre_tag = Keyword.get(opts, :pre_tag, "<b>")
+    post_tag = Keyword.get(opts, :post_tag, "</b>")
+    max_num_chars = Keyword.get(opts, :max_num_chars, @snippet_max_num_chars)
+
+    ref
+    |> Native.snippet_search(
+      fields,
+      search_terms,
+      limit,
+      snippet_fields,
+      pre_tag,
+      post_tag,
+      max_num_chars,
+      return_fields(opts),
+      filters(opts),
+      parser_options(opts)
+    )
+    |> case do
+      {:ok, results} ->
+        {:ok,
+         Enum.map(results, fn {doc, snippets} ->
+           %{document: doc, snippets: decode_snippets(snippets)}
+         end)}
 
       err ->
         err
     end
   end
 
-  @spec open_index(Tantex.Index.t(), String.t()) :: {:ok, Index.t()} | Native.error()
-  def open_index(%Index{__ref__: ref} = index, path) do
-    case Native.open_index(ref, path) do
+  @spec find_one(Index.t(), field_name(), String.t(), Keyword.t()) :: {:ok, map} | Native.error()
+  def find_one(%Index{__ref__: ref}, field, term, opts \\ [])
+      when is_binary(term) or is_integer(term) do
+    without_snapshot(opts, fn ->
+      Native.find_one_by_term(ref, string_field(field), term, return_fields(opts))
+    end)
+  end
+
+  # Pages are served within the first 10_000 hits: an `:offset` plus `:limit`
+  # beyond that fails with `{:error, {:result_window_too_large, _}}`.
+  @spec find_all(Index.t(), field_name(), String.t() | integer(), Keyword.t()) ::
+          {:ok, list(map()), non_neg_integer()} | Native.error()
+  def find_all(%Index{__ref__: ref}, field, term, opts \\ [])
+      when is_binary(term) or is_integer(term) do
+    limit = Keyword.get(opts, :limit, 10)
+    offset = Keyword.get(opts, :offset, 0)
+
+    without_snapshot(opts, fn ->
+      Native.find_all_by_term(ref, string_field(field), term, limit, offset, return_fields(opts))
+    end)
+  end
+
+  @spec find_by_keys(Index.t(), field_name(), list(String.t() | integer()), Keyword.t()) ::
+          {:ok, %{optional(String.t() | integer()) => map() | :not_found}} | Native.error()
+  def find_by_keys(%Index{__ref__: ref}, field, keys, opts \\ []) when is_list(keys) do
+    without_snapshot(opts, fn ->
+      Native.find_many_by_terms(ref, string_field(field), keys, return_fields(opts))
+    end)
+  end
+
+  @spec find_by_regex(Index.t(), field_name(), String.t(), non_neg_integer(), Keyword.t()) ::
+          {:ok, list(map())} | Native.error()
+  def find_by_regex(%Index{__ref__: ref}, field, pattern, limit, opts \\ [])
+      when is_binary(pattern) do
+    size_limit = Keyword.get(opts, :size_limit, @regex_size_limit)
+
+    without_snapshot(opts, fn ->
+      Native.regex_search(ref, string_field(field), pattern, limit, size_limit)
+    end)
+  end
+
+  # Sends every matching document to `pid` in chunks of `:chunk_size`, from a
+  # native thread, as `{:tantex_stream, stream_id, {:documents, docs}}`
+  # messages followed by `{:tantex_stream, stream_id, :done}` or
+  # `{:tantex_stream, stream_id, {:error, reason}}`.
+  #
+  # Each chunk must be acknowledged with `ack_stream/1` before the next one is
+  # read, so at most one chunk waits in the mailbox. The stream stops without
+  # further messages on `cancel_stream/1` or once the returned stream is
+  # garbage collected, so keep it in the receiving process. A chunk left
+  # unacknowledged for `:timeout` milliseconds ends the stream with
+  # `{:error, {:stream_timed_out, _}}`.
+  @spec stream(Index.t(), list(field_name()), String.t(), pid(), Keyword.t()) ::
+          {:ok, stream()} | Native.error()
+  def stream(%Index{__ref__: ref}, fields, search_terms, pid, opts \\ []) when is_pid(pid) do
+    fields = Enum.map(fields, &string_field/1)
+    chunk_size = Keyword.get(opts, :chunk_size, @stream_chunk_size)
+    timeout = Keyword.get(opts, :timeout, @stream_timeout)
+
+    ref
+    |> Native.stream_search(
+      fields,
+      search_terms,
+      filters(opts),
+      parser_options(opts),
+      return_fields(opts),
+      chunk_size,
+      timeout,
+      pid
+    )
+    |> case do
+      {:ok, stream_id, handle} -> {:ok, {stream_id, handle}}
+      err -> err
+    end
+  end
+
+  @spec ack_stream(stream()) :: :ok
+  def ack_stream({_stream_id, handle}) when is_reference(handle) do
+    Native.ack_stream(handle)
+  end
+
+  @spec cancel_stream(stream()) :: :ok
+  def cancel_stream({_stream_id, handle}) when is_reference(handle) do
+    Native.cancel_stream(handle)
+  end
+
+  # Pins the documents visible right now. Passing the snapshot as
+  # `:snapshot` to `find_many/5` or `find_page/5` runs the search against
+  # them even after later commits, until `release_snapshot/1` is called.
+  # The other searches always see the latest commit and fail with
+  # `{:error, {:unsupported_option, :snapshot}}` when given one.
+  @spec pin_snapshot(Index.t()) :: {:ok, reference()} | Native.error()
+  def pin_snapshot(%Index{__ref__: ref}) do
+    Native.pin_snapshot(ref)
+  end
+
+  @spec release_snapshot(reference()) :: :ok
+  def release_snapshot(snapshot) when is_reference(snapshot) do
+    Native.release_snapshot(snapshot)
+  end
+
+  @spec explain(Index.t(), list(field_name()), String.t(), {field_name(), term()}, Keyword.t()) ::
+          {:ok, explanation()} | Native.error()
+  def explain(%Index{__ref__: ref}, fields, search_terms, {key_field, key}, opts \\ []) do
+    fields = Enum.map(fields, &string_field/1)
+
+    without_snapshot(opts, fn ->
+      ref
+      |> Native.explain(fields, search_terms, parser_options(opts), string_field(key_field), key)
+      |> case do
+        {:ok, explanation} -> {:ok, decode_explanation(explanation)}
+        err -> err
+      end
+    end)
+  end
+
+  # `aggregations` names each aggregation, e.g. `[price: {:stats, :price}]`,
+  # and the results come back under the same names.
+  @spec aggregate(Index.t(), list(field_name()), String.t(), Keyword.t() | map(), Keyword.t()) ::
+          {:ok, map()} | Native.error()
+  def aggregate(%Index{__ref__: ref}, fields, search_terms, aggregations, opts \\ []) do
+    fields = Enum.map(fields, &string_field/1)
+    {names, aggregations} = Enum.unzip(aggregations)
+
+    without_snapshot(opts, fn ->
+      ref
+      |> Native.aggregate(
+        fields,
+        search_terms,
+        filters(opts),
+        parser_options(opts),
+        Enum.map(aggregations, &native_aggregation/1)
+      )
+      |> case do
+        {:ok, results} -> {:ok, decode_aggregations(names, aggregations, results)}
+        err -> err
+      end
+    end)
+  end
+
+  # Collapses the hits on `group_field`, an integer fast field or a string
+  # field, keeping the best `:per_group` hits of the best `:groups` groups.
+  @spec find_grouped(Index.t(), list(field_name()), String.t(), field_name(), Keyword.t()) ::
+          {:ok, list(%{key: term(), hit_count: non_neg_integer(), documents: list(map())})}
+          | Native.error()
+  def find_grouped(%Index{__ref__: ref}, fields, search_terms, group_field, opts \\ []) do
+    fields = Enum.map(fields, &string_field/1)
+    groups = Keyword.get(opts, :groups, 10)
+    per_group = Keyword.get(opts, :per_group, 1)
+
+    without_snapshot(opts, fn ->
+      ref
+      |> Native.collapse_search(
+        fields,
+        search_terms,
+        filters(opts),
+        parser_options(opts),
+        string_field(group_field),
+        groups,
+        per_group,
+        return_fields(opts)
+      )
+      |> case do
+        {:ok, groups} ->
+          {:ok,
+           Enum.map(groups, fn {key, hit_count, docs} ->
+             %{key: key, hit_count: hit_count, documents: docs}
+           end)}
+
+        err ->
+          err
+      end
+    end)
+  end
+
+  @spec parse_query(Index.t(), list(field_name()), String.t(), Keyword.t()) ::
+          {:ok, query_tree(), String.t()} | Native.error()
+  def parse_query(%Index{__ref__: ref}, fields, search_terms, opts \\ []) do
+    fields = Enum.map(fields, &string_field/1)
+
+    Native.parse_only(ref, fields, search_terms, parser_options(opts))
+  end
+
+  # `:max_snapshots` caps how many snapshots and streams may pin a searcher at
+  # once; the index keeps that many searchers on top of one per CPU.
+  @spec open_index(Tantex.Index.t(), String.t(), Keyword.t()) ::
+          {:ok, Index.t()} | Native.error()
+  def open_index(%Index{__ref__: ref} = index, path, opts \\ []) do
+    max_snapshots = Keyword.get(opts, :max_snapshots, @max_snapshots)
+
+    case Native.open_index(ref, path, max_snapshots) do
       :ok -> {:ok, %Index{index | path: path}}
       err -> err
     end
@@ -102,6 +396,148 @@ defmodule Tantex.Index do
     Native.finalize_schema(ref)
   end
 
+  # An empty list tells the NIF to return every stored field.
+  defp without_snapshot(opts, search) do
+    if Keyword.has_key?(opts, :snapshot) do
+      {:error, {:unsupported_option, :snapshot}}
+    else
+      search.()
+    end
+  end
+
+  defp return_fields(opts) do
+    opts
+    |> Keyword.get(:return_fields, [])
+    |> Enum.map(&string_field/1)
+  end
+
+  # `:boosts` accepts a keyword list or map, e.g. `[title: 3, body: 1]`.
+  defp parser_options(opts) do
+    conjunction = Keyword.get(opts, :conjunction, false)
+
+    boosts =
+      opts
+      |> Keyword.get(:boosts, [])
+      |> Enum.map(fn {field, boost} -> {string_field(field), boost / 1} end)
+
+    {conjunction, boosts}
+  end
+
+  defp filters(opts) do
+    opts
+    |> Keyword.get(:filters, [])
+    |> Enum.map(&native_filter/1)
+  end
+
+  defp native_filter({:exists, field}), do: {:exists, string_field(field)}
+  defp native_filter({:missing, field}), do: {:missing, string_field(field)}
+  defp native_filter({:in, field, values}) when is_list(values),
+    do: {:in, string_field(field), values}
+
+  defp native_aggregation({:stats, field}), do: {:stats, string_field(field)}
+
+  defp native_aggregation({:histogram, field, interval}),
+    do: native_aggregation({:histogram, field, interval, []})
+
+  defp native_aggregation({:histogram, field, interval, bounds}) do
+    min = bounds |> Keyword.get(:min) |> to_float()
+    max = bounds |> Keyword.get(:max) |> to_float()
+    {:histogram, string_field(field), interval / 1, min, max}
+  end
+
+  defp native_aggregation({:terms, field}), do: native_aggregation({:terms, field, []})
+
+  defp native_aggregation({:terms, field, opts}) do
+    size = Keyword.get(opts, :size, 10)
+    min_doc_count = Keyword.get(opts, :min_doc_count, 1)
+    {:terms, string_field(field), size, min_doc_count}
+  end
+
+  # Ranges are `{name, from, to}` with `from` inclusive, `to` exclusive and
+  # `nil` for an open end, e.g. `{:range, :price, [{"under 50", nil, 50}]}`.
+  defp native_aggregation({:range, field, ranges}) when is_list(ranges) do
+    ranges =
+      Enum.map(ranges, fn {name, from, to} ->
+        {to_string(name), to_float(from), to_float(to)}
+      end)
+
+    {:range, string_field(field), ranges}
+  end
+
+  defp native_aggregation({:cardinality, field}),
+    do: native_aggregation({:cardinality, field, []})
+
+  defp native_aggregation({:cardinality, field, opts}) do
+    precision = Keyword.get(opts, :precision, @cardinality_precision)
+    {:cardinality, string_field(field), precision}
+  end
+
+  defp native_aggregation({:percentiles, field}),
+    do: native_aggregation({:percentiles, field, []})
+
+  defp native_aggregation({:percentiles, field, opts} = aggregation) do
+    percents = aggregation |> percents() |> Enum.map(&to_float/1)
+    compression = opts |> Keyword.get(:compression, @percentiles_compression) |> to_float()
+    {:percentiles, string_field(field), percents, compression}
+  end
+
+  defp percents({:percentiles, _field}), do: @percentiles
+  defp percents({:percentiles, _field, opts}), do: Keyword.get(opts, :percents, @percentiles)
+
+  defp decode_aggregations(names, aggregations, results) do
+    [names, aggregations, results]
+    |> Enum.zip()
+    |> Map.new(fn {name, aggregation, result} ->
+      {name, decode_aggregation(aggregation, result)}
+    end)
+  end
+
+  # Percentiles are keyed by the percents exactly as they were passed, e.g.
+  # `results.percentiles[50]`, rather than by the floats sent to the NIF.
+  defp decode_aggregation(aggregation, {:percentiles, values}) do
+    aggregation
+    |> percents()
+    |> Enum.zip(Enum.map(values, fn {_percent, value} -> value end))
+    |> Map.new()
+  end
+
+  defp decode_aggregation(_aggregation, result), do: decode_aggregation(result)
+
+  defp decode_aggregation({:stats, {count, min, max, sum, avg}}),
+    do: %{count: count, min: min, max: max, sum: sum, avg: avg}
+
+  defp decode_aggregation({:histogram, buckets}),
+    do: Enum.map(buckets, fn {key, doc_count} -> %{key: key, doc_count: doc_count} end)
+
+  defp decode_aggregation({:terms, buckets}),
+    do: Enum.map(buckets, fn {key, doc_count} -> %{key: key, doc_count: doc_count} end)
+
+  defp decode_aggregation({:range, buckets}) do
+    Enum.map(buckets, fn {name, from, to, doc_count} ->
+      %{name: name, from: from, to: to, doc_count: doc_count}
+    end)
+  end
+
+  defp decode_aggregation({:cardinality, estimate}), do: estimate
+
+  defp to_float(nil), do: nil
+  defp to_float(x), do: x / 1
+
+  defp decode_snippets(snippets) do
+    Map.new(snippets, fn {field_name, html, fragment, highlighted} ->
+      {field_name, %{html: html, fragment: fragment, highlighted: highlighted}}
+    end)
+  end
+
+  defp decode_explanation({description, matched, score, details}) do
+    %{
+      description: description,
+      matched: matched,
+      score: score,
+      details: Enum.map(details, &decode_explanation/1)
+    }
+  end
+
   defp string_field(x) when is_binary(x), do: x
   defp string_field(x) when is_atom(x), do: to_string(x)
   defp string_field(%Field{name: name}), do: to_string(name)
@@ -2,7 +2,9 @@ defmodule Tantex.Native do
   @moduledoc false
   use Rustler, otp_app: :tantex, crate: "tantex_native"
 
-  @type error :: {:error, {atom, String.t()}}
+  # Most reasons carry a message. Invalid query patterns carry a tuple
+  # instead, e.g. `{:invalid_query_format, {:field_does_not_exist, "nope"}}`.
+  @type error :: {:error, {atom, String.t() | tuple()}}
 
   @doc """
 
@@ -30,17 +32,92 @@ defmodule Tantex.Native do
 
   def new_schema_index(), do: err()
 
-  def add_field(_index_ref, _field_name, _kind, _stored, _fast), do: err()
+  def add_field(_index_ref, _field_name, _kind, _stored, _fast, _multi), do: err()
 
   def finalize_schema(_index_ref), do: err()
 
-  def open_index(_index_ref, _index_path), do: err()
-
-  def write_documents(_index_ref, _encoded_docs, _heap_size), do: err()
-
-  def limit_search(_index_ref, _fields, _search_terms, _limit), do: err()
-
-  def find_one_by_term(_index_ref, _field_name, _term), do: err()
+  def open_index(_index_ref, _index_path, _max_snapshots), do: err()
+
+  def write_documents(_index_ref, _docs, _heap_size), do: err()
+
+  def limit_search(
+        _index_ref,
+        _fields,
+        _search_terms,
+        _limit,
+        _return_fields,
+        _filters,
+        _parser_options,
+        _aggregations,
+        _cursor,
+        _snapshot
+      ),
+      do: err()
+
+  def find_one_by_term(_index_ref, _field_name, _term, _return_fields), do: err()
+
+  def find_all_by_term(_index_ref, _field_name, _term, _limit, _offset, _return_fields),
+    do: err()
+
+  def find_many_by_terms(_index_ref, _field_name, _terms, _return_fields), do: err()
+
+  def snippet_search(
+        _index_ref,
+        _fields,
+        _search_terms,
+        _limit,
+        _snippet_fields,
+        _pre_tag,
+        _post_tag,
+        _max_num_chars,
+        _return_fields,
+        _filters,
+        _parser_options
+      ),
+      do: err()
+
+  def regex_search(_index_ref, _field_name, _pattern, _limit, _size_limit), do: err()
+
+  def explain(_index_ref, _fields, _search_terms, _parser_options, _key_field, _key), do: err()
+
+  def collapse_search(
+        _index_ref,
+        _fields,
+        _search_terms,
+        _filters,
+        _parser_options,
+        _collapse_field,
+        _groups,
+        _per_group,
+        _return_fields
+      ),
+      do: err()
+
+  def pin_snapshot(_index_ref), do: err()
+
+  def release_snapshot(_snapshot_ref), do: err()
+
+  def stream_search(
+        _index_ref,
+        _fields,
+        _search_terms,
+        _filters,
+        _parser_options,
+        _return_fields,
+        _chunk_size,
+        _timeout,
+        _pid
+      ),
+      do: err()
+
+  def ack_stream(_stream_ref), do: err()
+
+  def cancel_stream(_stream_ref), do: err()
+
+  def parse_only(_index_ref, _fields, _search_terms, _parser_options), do: err()
+
+  def aggregate(_index_ref, _fields, _search_terms, _filters, _parser_options, _aggregations),
+    do: err()
 
   defp err(), do: :erlang.nif_error(:nif_not_loaded)
 end
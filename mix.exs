@@ -32,8 +32,7 @@ defmodule Tantex.MixProject do
   # Run "mix help deps" to learn about dependencies.
   defp deps do
     [
-      {:rustler, "~> 0.18.0"},
-      {:jason, "~> 1.1"}
+      {:rustler, "~> 0.18.0"}
     ]
   end
 end
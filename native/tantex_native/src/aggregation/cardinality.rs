@@ -0,0 +1,142 @@
+use rustler::{Encoder, Env, Term};
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+// A HyperLogLog sketch with 2^precision one-byte registers. The standard error
+// of the estimate is about 1.04 / sqrt(2^precision), e.g. 0.8% at the default
+// precision of 14, which takes 16KB per segment. Sketches merge by keeping
+// the larger register, so merged segments estimate exactly as one sketch.
+pub struct Cardinality {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl Cardinality {
+    pub fn new(precision: u8) -> Cardinality {
+        Cardinality {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn add(&mut self, value: u64) {
+        let hash = mix(value);
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = hash << self.precision;
+        let max_rank = 64 - self.precision + 1;
+        let rank = (rest.leading_zeros() as u8 + 1).min(max_rank);
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Cardinality) {
+        for (register, other_register) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *other_register > *register {
+                *register = *other_register;
+            }
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-i32::from(*register)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Linear counting is more accurate while many registers are empty.
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+// splitmix64's finalizer; fast field values are often sequential ids, so they
+// are mixed before their bits are used as a hash.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Encoded as the estimated number of distinct values.
+impl Encoder for Cardinality {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.estimate().encode(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(values: std::ops::Range<u64>) -> Cardinality {
+        let mut cardinality = Cardinality::new(14);
+        for value in values {
+            cardinality.add(value);
+        }
+        cardinality
+    }
+
+    fn assert_close(estimate: u64, expected: u64, tolerance: f64) {
+        let error = (estimate as f64 - expected as f64).abs() / expected as f64;
+        assert!(
+            error <= tolerance,
+            "estimate {} is off {} by {:.4}",
+            estimate,
+            expected,
+            error
+        );
+    }
+
+    #[test]
+    fn estimates_zero_for_an_empty_sketch() {
+        assert_eq!(Cardinality::new(14).estimate(), 0);
+    }
+
+    #[test]
+    fn counts_small_sets_almost_exactly() {
+        assert_close(sketch(0..100).estimate(), 100, 0.01);
+    }
+
+    #[test]
+    fn estimates_large_sets_within_the_standard_error() {
+        assert_close(sketch(0..1_000_000).estimate(), 1_000_000, 0.03);
+    }
+
+    #[test]
+    fn ignores_repeated_values() {
+        let mut cardinality = sketch(0..10_000);
+        let once = cardinality.estimate();
+        for value in 0..10_000 {
+            cardinality.add(value);
+        }
+        assert_eq!(cardinality.estimate(), once);
+    }
+
+    #[test]
+    fn merges_into_the_sketch_of_the_union() {
+        let mut merged = sketch(0..60_000);
+        merged.merge(&sketch(40_000..100_000));
+        assert_eq!(merged.registers, sketch(0..100_000).registers);
+        assert_close(merged.estimate(), 100_000, 0.03);
+    }
+}
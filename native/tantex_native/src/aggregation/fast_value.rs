@@ -0,0 +1,71 @@
+use tantivy::fastfield::FastFieldReader;
+use tantivy::schema::{Field, FieldType, Schema};
+use tantivy::{DocId, SegmentReader};
+
+use super::super::tantex_error::TantexError;
+use super::super::utils::fetch_field;
+
+// Aggregations read u64 and i64 fast fields alike, as f64.
+//
+// Fast fields are single-valued and hold 0 for documents that never had a
+// value, so those documents are aggregated as 0. Pass an `{:exists, field}`
+// filter to leave them out.
+#[derive(Clone, Copy)]
+pub struct FastField {
+    pub field: Field,
+    signed: bool,
+}
+
+pub fn fetch_fast_field(schema: &Schema, field_name: &str) -> Result<FastField, TantexError> {
+    let field = fetch_field(schema, field_name)?;
+    match schema.get_field_entry(field).field_type() {
+        FieldType::U64(options) if options.is_fast() => Ok(FastField {
+            field,
+            signed: false,
+        }),
+        FieldType::I64(options) if options.is_fast() => Ok(FastField {
+            field,
+            signed: true,
+        }),
+        _ => Err(TantexError::FieldNotFast(field_name.to_string())),
+    }
+}
+
+impl FastField {
+    pub fn signed(&self) -> bool {
+        self.signed
+    }
+}
+
+pub enum FastValueReader {
+    U64(FastFieldReader<u64>),
+    I64(FastFieldReader<i64>),
+}
+
+impl FastValueReader {
+    pub fn open(reader: &SegmentReader, fast_field: FastField) -> tantivy::Result<FastValueReader> {
+        if fast_field.signed {
+            let i64_reader = reader.fast_field_reader::<i64>(fast_field.field)?;
+            Ok(FastValueReader::I64(i64_reader))
+        } else {
+            let u64_reader = reader.fast_field_reader::<u64>(fast_field.field)?;
+            Ok(FastValueReader::U64(u64_reader))
+        }
+    }
+
+    pub fn get(&self, doc: DocId) -> f64 {
+        match self {
+            FastValueReader::U64(reader) => reader.get(doc) as f64,
+            FastValueReader::I64(reader) => reader.get(doc) as f64,
+        }
+    }
+
+    // The raw 64 bits of the value, for aggregations that only compare values
+    // for equality and must not lose precision to f64.
+    pub fn get_bits(&self, doc: DocId) -> u64 {
+        match self {
+            FastValueReader::U64(reader) => reader.get(doc),
+            FastValueReader::I64(reader) => reader.get(doc) as u64,
+        }
+    }
+}
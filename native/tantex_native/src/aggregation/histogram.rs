@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use rustler::{Encoder, Env, Term};
+
+// Caps the number of buckets that bounds may ask for, since every bucket
+// between them is returned even when empty.
+pub const MAX_BOUNDED_BUCKETS: f64 = 65_536.0;
+
+// Buckets are keyed by `floor(value / interval) * interval`. Values outside
+// `min..=max` are left out. Timestamps are indexed as i64, so date
+// histograms are histograms over a seconds (or millis) field.
+#[derive(Clone, Copy)]
+pub struct HistogramOptions {
+    pub interval: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+pub struct Histogram {
+    options: HistogramOptions,
+    counts: BTreeMap<i64, u64>,
+}
+
+impl Histogram {
+    pub fn new(options: HistogramOptions) -> Histogram {
+        Histogram {
+            options,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        let below = self.options.min.map_or(false, |min| value < min);
+        let above = self.options.max.map_or(false, |max| value > max);
+        if below || above {
+            return;
+        }
+        *self.counts.entry(self.bucket(value)).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in other.counts.iter() {
+            *self.counts.entry(*bucket).or_insert(0) += count;
+        }
+    }
+
+    fn bucket(&self, value: f64) -> i64 {
+        (value / self.options.interval).floor() as i64
+    }
+
+    // With both bounds set every bucket between them is listed, so charts get
+    // a stable x axis; otherwise only buckets holding documents are.
+    fn buckets(&self) -> Vec<(f64, u64)> {
+        let interval = self.options.interval;
+        match (self.options.min, self.options.max) {
+            (Some(min), Some(max)) => (self.bucket(min)..=self.bucket(max))
+                .map(|bucket| {
+                    let count = self.counts.get(&bucket).cloned().unwrap_or(0);
+                    (bucket as f64 * interval, count)
+                })
+                .collect(),
+            _ => self
+                .counts
+                .iter()
+                .map(|(bucket, count)| (*bucket as f64 * interval, *count))
+                .collect(),
+        }
+    }
+}
+
+// Encoded as a list of `{key, doc_count}` ordered by key.
+impl Encoder for Histogram {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.buckets().encode(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(interval: f64, min: Option<f64>, max: Option<f64>, values: &[f64]) -> Histogram {
+        let mut histogram = Histogram::new(HistogramOptions { interval, min, max });
+        for value in values {
+            histogram.add(*value);
+        }
+        histogram
+    }
+
+    #[test]
+    fn keys_buckets_by_their_lower_bound() {
+        let histogram = histogram(10.0, None, None, &[0.0, 9.9, 10.0, 25.0, -0.5, -10.0]);
+        assert_eq!(
+            histogram.buckets(),
+            vec![(-10.0, 2), (0.0, 2), (10.0, 1), (20.0, 1)]
+        );
+    }
+
+    #[test]
+    fn lists_only_filled_buckets_without_both_bounds() {
+        let histogram = histogram(5.0, Some(0.0), None, &[1.0, 22.0]);
+        assert_eq!(histogram.buckets(), vec![(0.0, 1), (20.0, 1)]);
+    }
+
+    #[test]
+    fn lists_every_bucket_between_the_bounds() {
+        let histogram = histogram(10.0, Some(5.0), Some(35.0), &[12.0, 35.0]);
+        assert_eq!(
+            histogram.buckets(),
+            vec![(0.0, 0), (10.0, 1), (20.0, 0), (30.0, 1)]
+        );
+    }
+
+    #[test]
+    fn leaves_out_values_outside_the_bounds() {
+        let histogram = histogram(10.0, Some(5.0), Some(35.0), &[4.9, 5.0, 35.0, 35.1]);
+        assert_eq!(
+            histogram.buckets(),
+            vec![(0.0, 1), (10.0, 0), (20.0, 0), (30.0, 1)]
+        );
+    }
+
+    #[test]
+    fn merges_counts_per_bucket() {
+        let mut merged = histogram(10.0, None, None, &[1.0, 11.0]);
+        merged.merge(&histogram(10.0, None, None, &[2.0, 31.0]));
+        assert_eq!(merged.buckets(), vec![(0.0, 2), (10.0, 1), (30.0, 1)]);
+    }
+}
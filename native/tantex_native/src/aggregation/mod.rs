@@ -0,0 +1,349 @@
+pub mod cardinality;
+pub mod fast_value;
+pub mod histogram;
+pub mod percentiles;
+pub mod ranges;
+pub mod stats;
+pub mod top_terms;
+
+use rustler::types::atom::Atom;
+use rustler::types::tuple::get_tuple;
+use rustler::{Encoder, Env, Term};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::schema::Schema;
+use tantivy::{DocId, Score, SegmentLocalId, SegmentReader};
+
+use self::cardinality::{Cardinality, MAX_PRECISION, MIN_PRECISION};
+use self::fast_value::{fetch_fast_field, FastField, FastValueReader};
+use self::histogram::{Histogram, HistogramOptions, MAX_BOUNDED_BUCKETS};
+use self::percentiles::{Percentiles, MAX_COMPRESSION};
+use self::ranges::{NamedRange, RangeCounts};
+use self::stats::Stats;
+use self::top_terms::{fetch_term_field, SegmentTopTerms, TopTerms, TopTermsOptions};
+use super::atoms;
+use super::tantex_error::TantexError;
+
+pub enum Aggregation {
+    Stats(FastField),
+    Histogram(FastField, HistogramOptions),
+    Terms(TopTermsOptions),
+    Range(FastField, Vec<NamedRange>),
+    Cardinality(FastField, u8),
+    Percentiles(FastField, Vec<f64>, f64),
+}
+
+pub enum AggregationResult {
+    Stats(Stats),
+    Histogram(Histogram),
+    Terms(TopTerms),
+    Range(RangeCounts),
+    Cardinality(Cardinality),
+    Percentiles(Percentiles),
+}
+
+enum SegmentAggregation {
+    Stats(FastValueReader, Stats),
+    Histogram(FastValueReader, Histogram),
+    Terms(SegmentTopTerms, TopTermsOptions),
+    Range(FastValueReader, RangeCounts),
+    Cardinality(FastValueReader, Cardinality),
+    Percentiles(FastValueReader, Percentiles),
+}
+
+pub fn decode_aggregations(
+    schema: &Schema,
+    terms: Vec<Term>,
+) -> Result<Vec<Aggregation>, TantexError> {
+    let mut aggregations: Vec<Aggregation> = Vec::with_capacity(terms.len());
+    for term in terms {
+        aggregations.push(decode_aggregation(schema, term)?);
+    }
+    Ok(aggregations)
+}
+
+// Aggregations arrive as tagged tuples:
+//
+//   {:stats, "price"}
+//   {:histogram, "price", 10.0, nil, nil}
+//   {:terms, "brand", 10, 1}
+//   {:range, "price", [{"cheap", nil, 50.0}, {"expensive", 50.0, nil}]}
+//   {:cardinality, "user_id", 14}
+//   {:percentiles, "latency", [50.0, 99.0], 100.0}
+fn decode_aggregation(schema: &Schema, term: Term) -> Result<Aggregation, TantexError> {
+    let invalid = || TantexError::InvalidAggregation(format!("{:?}", term));
+    let elements = get_tuple(term).map_err(|_| invalid())?;
+    let kind: Atom = match elements.first() {
+        Some(kind) => kind.decode().map_err(|_| invalid())?,
+        None => return Err(invalid()),
+    };
+    if kind == atoms::stats() && elements.len() == 2 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        Ok(Aggregation::Stats(fetch_fast_field(schema, &field_name)?))
+    } else if kind == atoms::histogram() && elements.len() == 5 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        let options = HistogramOptions {
+            interval: elements[2].decode().map_err(|_| invalid())?,
+            min: elements[3].decode().map_err(|_| invalid())?,
+            max: elements[4].decode().map_err(|_| invalid())?,
+        };
+        if options.interval.is_nan() || options.interval <= 0.0 {
+            return Err(invalid());
+        }
+        if let (Some(min), Some(max)) = (options.min, options.max) {
+            if min > max || (max - min) / options.interval > MAX_BOUNDED_BUCKETS {
+                return Err(invalid());
+            }
+        }
+        let fast_field = fetch_fast_field(schema, &field_name)?;
+        Ok(Aggregation::Histogram(fast_field, options))
+    } else if kind == atoms::terms() && elements.len() == 4 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        let options = TopTermsOptions {
+            field: fetch_term_field(schema, &field_name)?,
+            size: elements[2].decode().map_err(|_| invalid())?,
+            min_doc_count: elements[3].decode().map_err(|_| invalid())?,
+        };
+        Ok(Aggregation::Terms(options))
+    } else if kind == atoms::range() && elements.len() == 3 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        let bounds: Vec<(String, Option<f64>, Option<f64>)> =
+            elements[2].decode().map_err(|_| invalid())?;
+        let ranges: Vec<NamedRange> = bounds
+            .into_iter()
+            .map(|(name, from, to)| NamedRange { name, from, to })
+            .collect();
+        let fast_field = fetch_fast_field(schema, &field_name)?;
+        Ok(Aggregation::Range(fast_field, ranges))
+    } else if kind == atoms::cardinality() && elements.len() == 3 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        let precision: u8 = elements[2].decode().map_err(|_| invalid())?;
+        if precision < MIN_PRECISION || precision > MAX_PRECISION {
+            return Err(invalid());
+        }
+        let fast_field = fetch_fast_field(schema, &field_name)?;
+        Ok(Aggregation::Cardinality(fast_field, precision))
+    } else if kind == atoms::percentiles() && elements.len() == 4 {
+        let field_name: String = elements[1].decode().map_err(|_| invalid())?;
+        let percents: Vec<f64> = elements[2].decode().map_err(|_| invalid())?;
+        let compression: f64 = elements[3].decode().map_err(|_| invalid())?;
+        let valid_percent = |percent: &f64| *percent >= 0.0 && *percent <= 100.0;
+        let valid_compression = compression > 0.0 && compression <= MAX_COMPRESSION;
+        if !percents.iter().all(valid_percent) || !valid_compression {
+            return Err(invalid());
+        }
+        let fast_field = fetch_fast_field(schema, &field_name)?;
+        Ok(Aggregation::Percentiles(fast_field, percents, compression))
+    } else {
+        Err(invalid())
+    }
+}
+
+impl Aggregation {
+    fn for_segment(&self, reader: &SegmentReader) -> tantivy::Result<SegmentAggregation> {
+        match self {
+            Aggregation::Stats(fast_field) => {
+                let values = FastValueReader::open(reader, *fast_field)?;
+                Ok(SegmentAggregation::Stats(values, Stats::new()))
+            }
+            Aggregation::Histogram(fast_field, options) => {
+                let values = FastValueReader::open(reader, *fast_field)?;
+                let histogram = Histogram::new(*options);
+                Ok(SegmentAggregation::Histogram(values, histogram))
+            }
+            Aggregation::Terms(options) => {
+                let top_terms = SegmentTopTerms::new(reader, *options);
+                Ok(SegmentAggregation::Terms(top_terms, *options))
+            }
+            Aggregation::Range(fast_field, ranges) => {
+                let values = FastValueReader::open(reader, *fast_field)?;
+                Ok(SegmentAggregation::Range(values, RangeCounts::new(ranges)))
+            }
+            Aggregation::Cardinality(fast_field, precision) => {
+                let values = FastValueReader::open(reader, *fast_field)?;
+                let cardinality = Cardinality::new(*precision);
+                Ok(SegmentAggregation::Cardinality(values, cardinality))
+            }
+            Aggregation::Percentiles(fast_field, percents, compression) => {
+                let values = FastValueReader::open(reader, *fast_field)?;
+                let percentiles = Percentiles::new(percents, *compression);
+                Ok(SegmentAggregation::Percentiles(values, percentiles))
+            }
+        }
+    }
+
+    // The result for a search that matched nothing; segment results are
+    // merged into it.
+    fn empty(&self) -> AggregationResult {
+        match self {
+            Aggregation::Stats(_) => AggregationResult::Stats(Stats::new()),
+            Aggregation::Histogram(_, options) => {
+                AggregationResult::Histogram(Histogram::new(*options))
+            }
+            Aggregation::Terms(options) => AggregationResult::Terms(TopTerms::new(*options)),
+            Aggregation::Range(_, ranges) => AggregationResult::Range(RangeCounts::new(ranges)),
+            Aggregation::Cardinality(_, precision) => {
+                AggregationResult::Cardinality(Cardinality::new(*precision))
+            }
+            Aggregation::Percentiles(_, percents, compression) => {
+                AggregationResult::Percentiles(Percentiles::new(percents, *compression))
+            }
+        }
+    }
+}
+
+impl AggregationResult {
+    fn merge(&mut self, other: AggregationResult) {
+        match (self, other) {
+            (AggregationResult::Stats(stats), AggregationResult::Stats(other)) => {
+                stats.merge(&other)
+            }
+            (AggregationResult::Histogram(histogram), AggregationResult::Histogram(other)) => {
+                histogram.merge(&other)
+            }
+            (AggregationResult::Terms(top_terms), AggregationResult::Terms(other)) => {
+                top_terms.merge(other)
+            }
+            (AggregationResult::Range(range_counts), AggregationResult::Range(other)) => {
+                range_counts.merge(&other)
+            }
+            (
+                AggregationResult::Cardinality(cardinality),
+                AggregationResult::Cardinality(other),
+            ) => cardinality.merge(&other),
+            (
+                AggregationResult::Percentiles(percentiles),
+                AggregationResult::Percentiles(other),
+            ) => percentiles.merge(&other),
+            _ => unreachable!("segments yield the same kind of result per aggregation"),
+        }
+    }
+}
+
+impl SegmentAggregation {
+    fn collect(&mut self, doc: DocId) {
+        match self {
+            SegmentAggregation::Stats(values, stats) => stats.add(values.get(doc)),
+            SegmentAggregation::Histogram(values, histogram) => histogram.add(values.get(doc)),
+            SegmentAggregation::Terms(top_terms, _) => top_terms.collect(doc),
+            SegmentAggregation::Range(values, range_counts) => range_counts.add(values.get(doc)),
+            SegmentAggregation::Cardinality(values, cardinality) => {
+                cardinality.add(values.get_bits(doc))
+            }
+            SegmentAggregation::Percentiles(values, percentiles) => {
+                percentiles.add(values.get(doc))
+            }
+        }
+    }
+
+    fn harvest(self) -> AggregationResult {
+        match self {
+            SegmentAggregation::Stats(_, stats) => AggregationResult::Stats(stats),
+            SegmentAggregation::Histogram(_, histogram) => AggregationResult::Histogram(histogram),
+            SegmentAggregation::Terms(top_terms, options) => {
+                AggregationResult::Terms(top_terms.harvest(options))
+            }
+            SegmentAggregation::Range(_, range_counts) => AggregationResult::Range(range_counts),
+            SegmentAggregation::Cardinality(_, cardinality) => {
+                AggregationResult::Cardinality(cardinality)
+            }
+            SegmentAggregation::Percentiles(_, percentiles) => {
+                AggregationResult::Percentiles(percentiles)
+            }
+        }
+    }
+}
+
+// Encoded as `{kind, result}` so the Elixir side can dispatch on the kind.
+impl Encoder for AggregationResult {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            AggregationResult::Stats(stats) => (atoms::stats(), stats.encode(env)).encode(env),
+            AggregationResult::Histogram(histogram) => {
+                (atoms::histogram(), histogram.encode(env)).encode(env)
+            }
+            AggregationResult::Terms(top_terms) => {
+                (atoms::terms(), top_terms.encode(env)).encode(env)
+            }
+            AggregationResult::Range(range_counts) => {
+                (atoms::range(), range_counts.encode(env)).encode(env)
+            }
+            AggregationResult::Cardinality(cardinality) => {
+                (atoms::cardinality(), cardinality.encode(env)).encode(env)
+            }
+            AggregationResult::Percentiles(percentiles) => {
+                (atoms::percentiles(), percentiles.encode(env)).encode(env)
+            }
+        }
+    }
+}
+
+// Runs every aggregation over the matching documents in a single pass. Only
+// fast fields and the term dictionary are read; no stored document is loaded.
+pub struct AggregationCollector {
+    aggregations: Vec<Aggregation>,
+}
+
+impl AggregationCollector {
+    pub fn new(aggregations: Vec<Aggregation>) -> AggregationCollector {
+        AggregationCollector { aggregations }
+    }
+}
+
+pub struct AggregationSegmentCollector {
+    aggregations: Vec<SegmentAggregation>,
+}
+
+impl Collector for AggregationCollector {
+    type Fruit = Vec<AggregationResult>;
+    type Child = AggregationSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentLocalId,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<AggregationSegmentCollector> {
+        let mut aggregations = Vec::with_capacity(self.aggregations.len());
+        for aggregation in self.aggregations.iter() {
+            aggregations.push(aggregation.for_segment(reader)?);
+        }
+        Ok(AggregationSegmentCollector { aggregations })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<Vec<AggregationResult>>,
+    ) -> tantivy::Result<Vec<AggregationResult>> {
+        let mut merged: Vec<AggregationResult> = self
+            .aggregations
+            .iter()
+            .map(|aggregation| aggregation.empty())
+            .collect();
+        for fruit in segment_fruits {
+            for (result, segment_result) in merged.iter_mut().zip(fruit.into_iter()) {
+                result.merge(segment_result);
+            }
+        }
+        Ok(merged)
+    }
+}
+
+impl SegmentCollector for AggregationSegmentCollector {
+    type Fruit = Vec<AggregationResult>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        for aggregation in self.aggregations.iter_mut() {
+            aggregation.collect(doc);
+        }
+    }
+
+    fn harvest(self) -> Vec<AggregationResult> {
+        self.aggregations
+            .into_iter()
+            .map(|aggregation| aggregation.harvest())
+            .collect()
+    }
+}
@@ -0,0 +1,252 @@
+use rustler::{Encoder, Env, Term};
+
+// The digest buffers up to `10 * compression` values per segment before
+// folding them into centroids, so compression is capped to bound that buffer.
+pub const MAX_COMPRESSION: f64 = 1_000.0;
+
+#[derive(Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+// A merging t-digest. Values are buffered and folded into centroids whose
+// weight is bounded by `4 * count * q * (1 - q) / compression`, so centroids
+// stay small near the tails and estimates of extreme percentiles stay tight.
+// Digests of different segments merge by folding their centroids together.
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> TDigest {
+        TDigest {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            min: std::f64::INFINITY,
+            max: std::f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+        if self.buffer.len() as f64 >= 10.0 * self.compression {
+            self.compress();
+        }
+    }
+
+    pub fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+
+    fn compress(&mut self) {
+        self.centroids = self.compressed();
+        self.buffer.clear();
+    }
+
+    fn compressed(&self) -> Vec<Centroid> {
+        let mut pending: Vec<Centroid> =
+            Vec::with_capacity(self.centroids.len() + self.buffer.len());
+        pending.extend_from_slice(&self.centroids);
+        pending.extend_from_slice(&self.buffer);
+        if pending.is_empty() {
+            return pending;
+        }
+        pending.sort_by(|a, b| {
+            a.mean
+                .partial_cmp(&b.mean)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let total: f64 = pending.iter().map(|centroid| centroid.weight).sum();
+        let mut merged: Vec<Centroid> = Vec::new();
+        let mut current = pending[0];
+        let mut weight_so_far = 0.0;
+        for next in pending.into_iter().skip(1) {
+            let proposed = current.weight + next.weight;
+            let q = (weight_so_far + proposed / 2.0) / total;
+            let max_weight = 4.0 * total * q * (1.0 - q) / self.compression;
+            if proposed <= max_weight {
+                current.mean += (next.mean - current.mean) * next.weight / proposed;
+                current.weight = proposed;
+            } else {
+                weight_so_far += current.weight;
+                merged.push(current);
+                current = next;
+            }
+        }
+        merged.push(current);
+        merged
+    }
+
+    // Interpolates between centroid centers, and between the outer centroids
+    // and the exact min and max. None when no value was added.
+    pub fn quantiles(&self, qs: &[f64]) -> Vec<Option<f64>> {
+        let centroids = self.compressed();
+        let total: f64 = centroids.iter().map(|centroid| centroid.weight).sum();
+        qs.iter()
+            .map(|q| {
+                if centroids.is_empty() {
+                    None
+                } else {
+                    Some(self.quantile(&centroids, total, *q))
+                }
+            })
+            .collect()
+    }
+
+    fn quantile(&self, centroids: &[Centroid], total: f64, q: f64) -> f64 {
+        let target = q * total;
+        let mut cumulative = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for centroid in centroids {
+            let center = cumulative + centroid.weight / 2.0;
+            if target < center {
+                let (lower_mean, lower_center) = previous.unwrap_or((self.min, 0.0));
+                return interpolate(lower_mean, lower_center, centroid.mean, center, target);
+            }
+            previous = Some((centroid.mean, center));
+            cumulative += centroid.weight;
+        }
+        let (last_mean, last_center) = previous.unwrap_or((self.max, total));
+        interpolate(last_mean, last_center, self.max, total, target)
+    }
+}
+
+fn interpolate(lower: f64, lower_at: f64, upper: f64, upper_at: f64, at: f64) -> f64 {
+    if upper_at <= lower_at {
+        return lower;
+    }
+    let t = ((at - lower_at) / (upper_at - lower_at)).max(0.0).min(1.0);
+    lower + t * (upper - lower)
+}
+
+pub struct Percentiles {
+    percents: Vec<f64>,
+    digest: TDigest,
+}
+
+impl Percentiles {
+    pub fn new(percents: &[f64], compression: f64) -> Percentiles {
+        Percentiles {
+            percents: percents.to_vec(),
+            digest: TDigest::new(compression),
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.digest.add(value)
+    }
+
+    pub fn merge(&mut self, other: &Percentiles) {
+        self.digest.merge(&other.digest)
+    }
+}
+
+// Encoded as a list of `{percent, value}` in the order the percents were
+// given; values are nil when no document matched.
+impl Encoder for Percentiles {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let qs: Vec<f64> = self
+            .percents
+            .iter()
+            .map(|percent| percent / 100.0)
+            .collect();
+        let values = self.digest.quantiles(&qs);
+        let results: Vec<(f64, Option<f64>)> = self
+            .percents
+            .iter()
+            .cloned()
+            .zip(values.into_iter())
+            .collect();
+        results.encode(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(values: std::ops::RangeInclusive<u32>) -> TDigest {
+        let mut digest = TDigest::new(100.0);
+        for value in values {
+            digest.add(f64::from(value));
+        }
+        digest
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64, tolerance: f64) {
+        let actual = actual.expect("a value");
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn has_no_quantiles_without_values() {
+        assert_eq!(TDigest::new(100.0).quantiles(&[0.5]), vec![None]);
+    }
+
+    #[test]
+    fn returns_the_only_value_for_every_quantile() {
+        let digest = digest(7..=7);
+        assert_eq!(
+            digest.quantiles(&[0.0, 0.5, 1.0]),
+            vec![Some(7.0), Some(7.0), Some(7.0)]
+        );
+    }
+
+    #[test]
+    fn pins_the_extremes_to_the_exact_min_and_max() {
+        let digest = digest(1..=100_000);
+        assert_eq!(
+            digest.quantiles(&[0.0, 1.0]),
+            vec![Some(1.0), Some(100_000.0)]
+        );
+    }
+
+    #[test]
+    fn estimates_quantiles_of_a_uniform_distribution() {
+        let digest = digest(1..=100_000);
+        let quantiles = digest.quantiles(&[0.01, 0.25, 0.5, 0.75, 0.99]);
+        assert_close(quantiles[0], 1_000.0, 100.0);
+        assert_close(quantiles[1], 25_000.0, 500.0);
+        assert_close(quantiles[2], 50_000.0, 500.0);
+        assert_close(quantiles[3], 75_000.0, 500.0);
+        assert_close(quantiles[4], 99_000.0, 100.0);
+    }
+
+    #[test]
+    fn merges_digests_of_disjoint_ranges() {
+        let mut merged = digest(1..=50_000);
+        merged.merge(&digest(50_001..=100_000));
+        let quantiles = merged.quantiles(&[0.0, 0.5, 0.99, 1.0]);
+        assert_eq!(quantiles[0], Some(1.0));
+        assert_close(quantiles[1], 50_000.0, 500.0);
+        assert_close(quantiles[2], 99_000.0, 100.0);
+        assert_eq!(quantiles[3], Some(100_000.0));
+    }
+
+    #[test]
+    fn keeps_far_fewer_centroids_than_values() {
+        let digest = digest(1..=100_000);
+        assert!(digest.compressed().len() < 1_000);
+    }
+}
@@ -0,0 +1,58 @@
+use rustler::{Encoder, Env, Term};
+
+// `from` is inclusive and `to` exclusive; either may be left open. Ranges may
+// overlap, in which case a document counts towards each of them.
+#[derive(Clone)]
+pub struct NamedRange {
+    pub name: String,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+}
+
+impl NamedRange {
+    fn contains(&self, value: f64) -> bool {
+        self.from.map_or(true, |from| value >= from) && self.to.map_or(true, |to| value < to)
+    }
+}
+
+pub struct RangeCounts {
+    ranges: Vec<NamedRange>,
+    counts: Vec<u64>,
+}
+
+impl RangeCounts {
+    pub fn new(ranges: &[NamedRange]) -> RangeCounts {
+        RangeCounts {
+            ranges: ranges.to_vec(),
+            counts: vec![0; ranges.len()],
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        for (range, count) in self.ranges.iter().zip(self.counts.iter_mut()) {
+            if range.contains(value) {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &RangeCounts) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+    }
+}
+
+// Encoded as a list of `{name, from, to, doc_count}` in the order the ranges
+// were given.
+impl Encoder for RangeCounts {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let buckets: Vec<Term<'a>> = self
+            .ranges
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(range, count)| (range.name.as_str(), range.from, range.to, *count).encode(env))
+            .collect();
+        buckets.encode(env)
+    }
+}
@@ -0,0 +1,48 @@
+use rustler::{Encoder, Env, Term};
+
+#[derive(Clone, Copy)]
+pub struct Stats {
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            count: 0,
+            min: std::f64::INFINITY,
+            max: std::f64::NEG_INFINITY,
+            sum: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+}
+
+// Encoded as `{count, min, max, sum, avg}`; min, max and avg are nil when no
+// document matched.
+impl Encoder for Stats {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let (min, max, avg) = if self.count == 0 {
+            (None, None, None)
+        } else {
+            let avg = self.sum / self.count as f64;
+            (Some(self.min), Some(self.max), Some(avg))
+        };
+        (self.count, min, max, self.sum, avg).encode(env)
+    }
+}
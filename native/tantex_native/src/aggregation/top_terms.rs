@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rustler::{Encoder, Env, Term};
+use tantivy::schema::{Field, FieldType, Schema};
+use tantivy::{DocId, SegmentReader};
+
+use super::super::tantex_error::TantexError;
+use super::super::term_ords::{is_raw_string_field, segment_term_ords, TermOrds};
+use super::super::utils::fetch_field;
+
+#[derive(Clone, Copy)]
+pub struct TopTermsOptions {
+    pub field: Field,
+    pub size: usize,
+    pub min_doc_count: u64,
+}
+
+// Terms are counted per value, so only untokenized `string` fields qualify:
+// a tokenized `text` field would give top tokens instead.
+pub fn fetch_term_field(schema: &Schema, field_name: &str) -> Result<Field, TantexError> {
+    let field = fetch_field(schema, field_name)?;
+    match schema.get_field_entry(field).field_type() {
+        FieldType::Str(_) if is_raw_string_field(schema, field) => Ok(field),
+        FieldType::Str(text_options) if text_options.get_indexing_options().is_some() => {
+            let message = format!("terms of tokenized field {:?}", field_name);
+            Err(TantexError::InvalidAggregation(message))
+        }
+        FieldType::Str(_) => Err(TantexError::FieldNotIndexed(field_name.to_string())),
+        field_type => Err(TantexError::TypeCannotBeSearched(field_type.value_type())),
+    }
+}
+
+// Each matching document adds one to the count of every term it holds; the
+// segment's doc-to-term table is shared with later searches, so a query costs
+// as much as the documents it matches. Every segment reports all of its
+// counted terms, which keeps the merged counts exact.
+pub struct SegmentTopTerms {
+    table: Arc<TermOrds>,
+    counts: Vec<u64>,
+}
+
+impl SegmentTopTerms {
+    pub fn new(reader: &SegmentReader, options: TopTermsOptions) -> SegmentTopTerms {
+        let table = segment_term_ords(reader, options.field);
+        let counts = vec![0; table.num_terms()];
+        SegmentTopTerms { table, counts }
+    }
+
+    pub fn collect(&mut self, doc: DocId) {
+        for &ord in self.table.ords(doc) {
+            self.counts[ord as usize] += 1;
+        }
+    }
+
+    pub fn harvest(self, options: TopTermsOptions) -> TopTerms {
+        let table = self.table;
+        let counts: HashMap<String, u64> = self
+            .counts
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, doc_count)| doc_count > 0)
+            .map(|(ord, doc_count)| (table.term(ord as u32).to_string(), doc_count))
+            .collect();
+        TopTerms { options, counts }
+    }
+}
+
+pub struct TopTerms {
+    options: TopTermsOptions,
+    counts: HashMap<String, u64>,
+}
+
+impl TopTerms {
+    pub fn new(options: TopTermsOptions) -> TopTerms {
+        TopTerms {
+            options,
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn merge(&mut self, other: TopTerms) {
+        for (key, doc_count) in other.counts {
+            *self.counts.entry(key).or_insert(0) += doc_count;
+        }
+    }
+
+    // Most frequent first; ties are broken by term so results are stable.
+    fn buckets(&self) -> Vec<(&str, u64)> {
+        let mut buckets: Vec<(&str, u64)> = self
+            .counts
+            .iter()
+            .filter(|&(_, &doc_count)| doc_count >= self.options.min_doc_count)
+            .map(|(key, doc_count)| (key.as_str(), *doc_count))
+            .collect();
+        buckets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        buckets.truncate(self.options.size);
+        buckets
+    }
+}
+
+// Encoded as a list of `{term, doc_count}`.
+impl Encoder for TopTerms {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.buckets().encode(env)
+    }
+}
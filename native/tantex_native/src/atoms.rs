@@ -19,14 +19,62 @@ rustler_atoms! {
     atom field_not_found;
     atom document_must_be_map;
     atom invalid_query_format;
+    atom syntax_error;
+    atom field_does_not_exist;
+    atom expected_integer;
+    atom only_excluding_clauses;
+    atom no_default_field;
+    atom field_has_no_positions;
+    atom range_with_phrase;
     atom invalid_document_json;
+    atom invalid_document_value;
     atom search_execution_failed;
     atom document_retrieval_failed;
+    atom document_encoding_failed;
     atom document_not_found;
+    atom not_found;
     atom schema_builder_not_found;
     atom schema_not_found;
     atom index_not_found;
     atom invalid_field_data;
+    atom invalid_regex;
+    atom snippet_generation_failed;
+    atom result_window_too_large;
+    atom field_not_indexed;
+    atom unknown_tokenizer;
+    atom term_has_no_tokens;
+    atom term_has_multiple_tokens;
+    atom invalid_filter;
+    atom invalid_aggregation;
+    atom field_not_fast;
+    atom invalid_cursor;
+    atom invalid_collapse_field;
+    atom too_many_snapshots;
+    atom snapshot_released;
+    atom snapshot_index_mismatch;
+    atom invalid_chunk_size;
+    atom stream_timed_out;
+    atom tantex_stream;
+    atom documents;
+    atom done;
+    atom exists;
+    atom missing;
+    atom in_ = "in";
+    atom boolean;
+    atom term;
+    atom phrase;
+    atom range;
+    atom boost;
+    atom all;
+    atom other;
+    atom must;
+    atom should;
+    atom must_not;
+    atom stats;
+    atom histogram;
+    atom terms;
+    atom cardinality;
+    atom percentiles;
     // atom __true__ = "true";
     // atom __false__ = "false";
 }
@@ -0,0 +1,262 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use rustler::{Encoder, Env, Term};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::schema::{Field, FieldType, Schema};
+use tantivy::{DocAddress, DocId, Score, SegmentLocalId, SegmentReader};
+
+use super::aggregation::fast_value::{fetch_fast_field, FastField, FastValueReader};
+use super::tantex_error::TantexError;
+use super::term_ords::{is_raw_string_field, segment_term_ords, TermOrds};
+use super::utils::fetch_field;
+
+#[derive(Clone, Copy)]
+pub enum CollapseKey {
+    Fast(FastField),
+    Term(Field),
+}
+
+// Integer fields are grouped through their fast field; string fields through
+// their term dictionary. Only untokenized `string` fields can be keys: a
+// tokenized `text` field has one term per token, not one per document.
+pub fn fetch_collapse_key(schema: &Schema, field_name: &str) -> Result<CollapseKey, TantexError> {
+    let field = fetch_field(schema, field_name)?;
+    match schema.get_field_entry(field).field_type() {
+        FieldType::U64(_) | FieldType::I64(_) => {
+            Ok(CollapseKey::Fast(fetch_fast_field(schema, field_name)?))
+        }
+        FieldType::Str(_) if is_raw_string_field(schema, field) => Ok(CollapseKey::Term(field)),
+        _ => Err(TantexError::InvalidCollapseField(field_name.to_string())),
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum GroupValue {
+    U64(u64),
+    I64(i64),
+    Str(String),
+}
+
+impl Encoder for GroupValue {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            GroupValue::U64(u) => u.encode(env),
+            GroupValue::I64(i) => i.encode(env),
+            GroupValue::Str(text) => text.encode(env),
+        }
+    }
+}
+
+// Ranked by descending score, ties broken by ascending doc address, and
+// ordered so that a max-heap keeps the worst hit on top.
+#[derive(Clone, Copy)]
+struct GroupHit(Score, DocAddress);
+
+impl Ord for GroupHit {
+    fn cmp(&self, other: &GroupHit) -> Ordering {
+        let DocAddress(segment_ord, doc) = self.1;
+        let DocAddress(other_segment_ord, other_doc) = other.1;
+        other
+            .0
+            .partial_cmp(&self.0)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| (segment_ord, doc).cmp(&(other_segment_ord, other_doc)))
+    }
+}
+
+impl PartialOrd for GroupHit {
+    fn partial_cmp(&self, other: &GroupHit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for GroupHit {
+    fn eq(&self, other: &GroupHit) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for GroupHit {}
+
+pub struct Group {
+    pub hit_count: u64,
+    best_score: Score,
+    hits: BinaryHeap<GroupHit>,
+}
+
+impl Group {
+    fn new() -> Group {
+        Group {
+            hit_count: 0,
+            best_score: std::f32::NEG_INFINITY,
+            hits: BinaryHeap::new(),
+        }
+    }
+
+    fn push(&mut self, hit: GroupHit, per_group: usize) {
+        self.hit_count += 1;
+        self.keep(hit, per_group);
+    }
+
+    fn merge(&mut self, other: Group, per_group: usize) {
+        self.hit_count += other.hit_count;
+        self.best_score = self.best_score.max(other.best_score);
+        for hit in other.hits {
+            self.keep(hit, per_group);
+        }
+    }
+
+    // Keeps the best `limit` hits: the worst one is swapped out once full.
+    fn keep(&mut self, hit: GroupHit, limit: usize) {
+        self.best_score = self.best_score.max(hit.0);
+        if self.hits.len() >= limit {
+            match self.hits.peek() {
+                Some(worst) if hit < *worst => {
+                    self.hits.pop();
+                }
+                _ => return,
+            }
+        }
+        self.hits.push(hit);
+    }
+
+    // The kept hits, best first.
+    pub fn hits(&self) -> Vec<(Score, DocAddress)> {
+        let mut hits: Vec<GroupHit> = self.hits.iter().cloned().collect();
+        hits.sort();
+        hits.into_iter()
+            .map(|GroupHit(score, address)| (score, address))
+            .collect()
+    }
+}
+
+// Documents without a value for a string key are grouped under `None`.
+pub type CollapsedGroups = Vec<(Option<GroupValue>, Group)>;
+
+// Groups the matching documents by `key` and keeps the best `per_group` hits
+// of each. Groups are ranked by their best hit, and the top `groups` are kept.
+pub struct CollapseCollector {
+    key: CollapseKey,
+    groups: usize,
+    per_group: usize,
+}
+
+impl CollapseCollector {
+    pub fn new(key: CollapseKey, groups: usize, per_group: usize) -> CollapseCollector {
+        CollapseCollector {
+            key,
+            groups,
+            per_group,
+        }
+    }
+}
+
+enum SegmentKeys {
+    Fast(FastValueReader, bool),
+    Term(Arc<TermOrds>),
+}
+
+impl SegmentKeys {
+    fn open(reader: &SegmentReader, key: CollapseKey) -> tantivy::Result<SegmentKeys> {
+        match key {
+            CollapseKey::Fast(fast_field) => {
+                let values = FastValueReader::open(reader, fast_field)?;
+                Ok(SegmentKeys::Fast(values, fast_field.signed()))
+            }
+            CollapseKey::Term(field) => Ok(SegmentKeys::Term(segment_term_ords(reader, field))),
+        }
+    }
+
+    // A document holding several values of a string key is grouped under
+    // its first term.
+    fn key_id(&self, doc: DocId) -> Option<u64> {
+        match self {
+            SegmentKeys::Fast(values, _) => Some(values.get_bits(doc)),
+            SegmentKeys::Term(table) => table.ords(doc).first().map(|&ord| u64::from(ord)),
+        }
+    }
+
+    fn value(&self, key_id: u64) -> GroupValue {
+        match self {
+            SegmentKeys::Fast(_, true) => GroupValue::I64(key_id as i64),
+            SegmentKeys::Fast(_, false) => GroupValue::U64(key_id),
+            SegmentKeys::Term(table) => GroupValue::Str(table.term(key_id as u32).to_string()),
+        }
+    }
+}
+
+pub struct CollapseSegmentCollector {
+    segment_local_id: SegmentLocalId,
+    keys: SegmentKeys,
+    per_group: usize,
+    groups: HashMap<Option<u64>, Group>,
+}
+
+impl Collector for CollapseCollector {
+    type Fruit = CollapsedGroups;
+    type Child = CollapseSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_local_id: SegmentLocalId,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<CollapseSegmentCollector> {
+        Ok(CollapseSegmentCollector {
+            segment_local_id,
+            keys: SegmentKeys::open(reader, self.key)?,
+            per_group: self.per_group,
+            groups: HashMap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<CollapsedGroups>,
+    ) -> tantivy::Result<CollapsedGroups> {
+        let mut merged: HashMap<Option<GroupValue>, Group> = HashMap::new();
+        for fruit in segment_fruits {
+            for (value, group) in fruit {
+                merged
+                    .entry(value)
+                    .or_insert_with(Group::new)
+                    .merge(group, self.per_group);
+            }
+        }
+        let mut groups: CollapsedGroups = merged.into_iter().collect();
+        groups.sort_by(|(_, a), (_, b)| {
+            b.best_score
+                .partial_cmp(&a.best_score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.hit_count.cmp(&a.hit_count))
+        });
+        groups.truncate(self.groups);
+        Ok(groups)
+    }
+}
+
+impl SegmentCollector for CollapseSegmentCollector {
+    type Fruit = CollapsedGroups;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let key_id = self.keys.key_id(doc);
+        let hit = GroupHit(score, DocAddress(self.segment_local_id, doc));
+        self.groups
+            .entry(key_id)
+            .or_insert_with(Group::new)
+            .push(hit, self.per_group);
+    }
+
+    fn harvest(self) -> CollapsedGroups {
+        let keys = self.keys;
+        self.groups
+            .into_iter()
+            .map(|(key_id, group)| (key_id.map(|key_id| keys.value(key_id)), group))
+            .collect()
+    }
+}
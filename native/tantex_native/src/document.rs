@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use rustler::types::binary::{Binary, OwnedBinary};
+use rustler::types::map::{map_new, MapIterator};
+use rustler::{Encoder, Env, Term};
+use tantivy::schema::{Document, Facet, Field, FieldType, FieldValue, Schema, Value};
+
+use super::tantex_error::TantexError;
+use super::utils::fetch_field;
+use TantexError::{DocumentEncodingFailed, DocumentMustBeMap, InvalidDocumentValue};
+
+pub struct DocumentEncoder<'s> {
+    schema: &'s Schema,
+    projection: Option<Vec<Field>>,
+    multi_valued: &'s [Field],
+}
+
+impl<'s> DocumentEncoder<'s> {
+    pub fn new(
+        schema: &'s Schema,
+        projection: Option<Vec<Field>>,
+        multi_valued: &'s [Field],
+    ) -> DocumentEncoder<'s> {
+        DocumentEncoder {
+            schema,
+            projection,
+            multi_valued,
+        }
+    }
+
+    pub fn encode_all<'a>(
+        &self,
+        env: Env<'a>,
+        docs: &[Document],
+    ) -> Result<Vec<Term<'a>>, TantexError> {
+        docs.iter().map(|doc| self.encode(env, doc)).collect()
+    }
+
+    // Fields declared with `multi: true` are always lists, even with a single
+    // value, and every other field is unwrapped to its value, so each field
+    // keeps the same type from one document to the next. A single-valued field
+    // only holds several values in documents written before it was declared
+    // that way; those come back as lists. Fields outside the projection are
+    // never encoded.
+    pub fn encode<'a>(&self, env: Env<'a>, doc: &Document) -> Result<Term<'a>, TantexError> {
+        let mut grouped: BTreeMap<Field, Vec<&Value>> = BTreeMap::new();
+        for field_value in doc.field_values() {
+            let field = field_value.field();
+            if self.is_projected(field) {
+                grouped
+                    .entry(field)
+                    .or_insert_with(Vec::new)
+                    .push(field_value.value());
+            }
+        }
+        let mut map = map_new(env);
+        for (field, values) in grouped {
+            let key = self.schema.get_field_name(field).encode(env);
+            let value = if values.len() == 1 && !self.multi_valued.contains(&field) {
+                encode_value(env, values[0])
+            } else {
+                let terms: Vec<Term<'a>> = values.iter().map(|v| encode_value(env, v)).collect();
+                terms.encode(env)
+            };
+            map = match map.map_put(key, value) {
+                Ok(map) => map,
+                Err(_) => {
+                    let field_name = self.schema.get_field_name(field);
+                    return Err(DocumentEncodingFailed(field_name.to_string()));
+                }
+            };
+        }
+        Ok(map)
+    }
+
+    fn is_projected(&self, field: Field) -> bool {
+        match self.projection {
+            Some(ref fields) => fields.contains(&field),
+            None => true,
+        }
+    }
+}
+
+fn encode_value<'a>(env: Env<'a>, value: &Value) -> Term<'a> {
+    match value {
+        Value::Str(text) => text.encode(env),
+        Value::U64(u) => u.encode(env),
+        Value::I64(i) => i.encode(env),
+        Value::Facet(facet) => facet.to_string().encode(env),
+        Value::Bytes(bytes) => encode_bytes(env, bytes),
+    }
+}
+
+fn encode_bytes<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    let mut binary = OwnedBinary::new(bytes.len()).expect("failed to allocate binary");
+    binary.as_mut_slice().copy_from_slice(bytes);
+    binary.release(env).encode(env)
+}
+
+// Keys may be atoms or binaries. Fields declared with `multi: true` take a
+// list of values, e.g. `%{"tag" => ["a", "b"]}`, or a single value; every
+// other field takes exactly one value.
+pub fn decode_document<'a>(
+    schema: &Schema,
+    multi_valued: &[Field],
+    term: Term<'a>,
+) -> Result<Document, TantexError> {
+    let iter = match MapIterator::new(term) {
+        Some(iter) => iter,
+        None => return Err(DocumentMustBeMap(format!("{:?}", term))),
+    };
+    let mut doc = Document::default();
+    for (key, value) in iter {
+        let field_name = decode_field_name(key, value)?;
+        let field = fetch_field(schema, &field_name)?;
+        let field_type = schema.get_field_entry(field).field_type();
+        match value.decode::<Vec<Term<'a>>>() {
+            Ok(_) if !multi_valued.contains(&field) => {
+                let value = format!("{:?}", value);
+                return Err(InvalidDocumentValue(field_name, value));
+            }
+            Ok(values) => {
+                for value in values {
+                    let decoded = decode_value(field_type, &field_name, value)?;
+                    doc.add(FieldValue::new(field, decoded));
+                }
+            }
+            Err(_) => {
+                let decoded = decode_value(field_type, &field_name, value)?;
+                doc.add(FieldValue::new(field, decoded));
+            }
+        }
+    }
+    Ok(doc)
+}
+
+// A key that is neither an atom nor a binary cannot name a field, so the
+// entry is rejected as a whole, naming the key.
+fn decode_field_name(key: Term, value: Term) -> Result<String, TantexError> {
+    if let Ok(name) = key.atom_to_string() {
+        return Ok(name);
+    }
+    match key.decode::<String>() {
+        Ok(name) => Ok(name),
+        Err(_) => Err(InvalidDocumentValue(
+            format!("{:?}", key),
+            format!("{:?}", value),
+        )),
+    }
+}
+
+fn decode_value(
+    field_type: &FieldType,
+    field_name: &str,
+    term: Term,
+) -> Result<Value, TantexError> {
+    let decoded = match field_type {
+        FieldType::Str(_) => term.decode::<String>().ok().map(Value::Str),
+        FieldType::U64(_) => term.decode::<u64>().ok().map(Value::U64),
+        FieldType::I64(_) => term.decode::<i64>().ok().map(Value::I64),
+        FieldType::HierarchicalFacet => term
+            .decode::<String>()
+            .ok()
+            .filter(|path| path.starts_with('/'))
+            .map(|path| Value::Facet(Facet::from_text(&path))),
+        FieldType::Bytes => term
+            .decode::<Binary>()
+            .ok()
+            .map(|binary| Value::Bytes(binary.as_slice().to_vec())),
+    };
+    match decoded {
+        Some(value) => Ok(value),
+        None => {
+            let value = format!("{:?}", term);
+            Err(InvalidDocumentValue(field_name.to_string(), value))
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+
+use rustler::{Encoder, Env, Term};
+use tantivy::query::{BooleanQuery, Occur, Query};
+use tantivy::schema::IndexRecordOption;
+use tantivy::{DocAddress, DocSet, Score, Searcher, SkipResult};
+
+use super::query::boost_query::BoostQuery;
+use super::tantex_error::TantexError;
+
+// tantivy 0.8 has no explanation API, so the tree is rebuilt here: every node
+// runs its own (sub)query's scorer against the document, boolean queries are
+// broken down per clause and leaf queries list the idf of their terms.
+pub struct Explanation {
+    pub description: String,
+    pub matched: bool,
+    pub score: Score,
+    pub details: Vec<Explanation>,
+}
+
+// Encoded as nested `{description, matched, score, details}` tuples.
+impl Encoder for Explanation {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (
+            self.description.encode(env),
+            self.matched.encode(env),
+            self.score.encode(env),
+            self.details.encode(env),
+        )
+            .encode(env)
+    }
+}
+
+pub fn explain(
+    searcher: &Searcher,
+    query: &Query,
+    doc_address: DocAddress,
+) -> Result<Explanation, TantexError> {
+    let (matched, score) = score_doc(searcher, query, doc_address)?;
+    if let Ok(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+        let mut details = Vec::with_capacity(boolean_query.clauses().len());
+        for (occur, clause) in boolean_query.clauses() {
+            let mut explanation = explain(searcher, &**clause, doc_address)?;
+            explanation.description =
+                format!("{} - {}", describe_occur(*occur), explanation.description);
+            details.push(explanation);
+        }
+        return Ok(Explanation {
+            description: "boolean query, sum of matching clauses".to_string(),
+            matched,
+            score,
+            details,
+        });
+    }
+    if let Ok(boost_query) = query.downcast_ref::<BoostQuery>() {
+        let inner = explain(searcher, boost_query.query(), doc_address)?;
+        return Ok(Explanation {
+            description: format!("boost: {}", boost_query.boost()),
+            matched,
+            score,
+            details: vec![inner],
+        });
+    }
+    Ok(Explanation {
+        description: format!("{:?}", query),
+        matched,
+        score,
+        details: explain_terms(searcher, query, doc_address),
+    })
+}
+
+fn score_doc(
+    searcher: &Searcher,
+    query: &Query,
+    doc_address: DocAddress,
+) -> Result<(bool, Score), TantexError> {
+    let DocAddress(segment_ord, doc) = doc_address;
+    let failed = |e| TantexError::SearchExecutionFailed(query.box_clone(), e);
+    let weight = query.weight(searcher, true).map_err(failed)?;
+    let mut scorer = weight
+        .scorer(searcher.segment_reader(segment_ord))
+        .map_err(failed)?;
+    match scorer.skip_next(doc) {
+        SkipResult::Reached => Ok((true, scorer.score())),
+        _ => Ok((false, 0.0)),
+    }
+}
+
+// One detail per query term, scored with the BM25 idf tantivy uses for it.
+fn explain_terms(searcher: &Searcher, query: &Query, doc_address: DocAddress) -> Vec<Explanation> {
+    let DocAddress(segment_ord, doc) = doc_address;
+    let segment_reader = searcher.segment_reader(segment_ord);
+    let num_docs = searcher.num_docs();
+    let mut term_set = BTreeSet::new();
+    query.query_terms(&mut term_set);
+    term_set
+        .iter()
+        .map(|term| {
+            let doc_freq = searcher.doc_freq(term);
+            let inverted_index = segment_reader.inverted_index(term.field());
+            let term_freq = match inverted_index.read_postings(term, IndexRecordOption::WithFreqs) {
+                Some(mut postings) => match postings.skip_next(doc) {
+                    SkipResult::Reached => postings.term_freq(),
+                    _ => 0,
+                },
+                None => 0,
+            };
+            let (n, df) = (num_docs as f32, doc_freq as f32);
+            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+            Explanation {
+                description: format!(
+                    "idf - term: {:?} - term_freq: {} - doc_freq: {} - num_docs: {}",
+                    term, term_freq, doc_freq, num_docs
+                ),
+                matched: term_freq > 0,
+                score: idf,
+                details: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+fn describe_occur(occur: Occur) -> &'static str {
+    match occur {
+        Occur::Must => "must",
+        Occur::Should => "should",
+        Occur::MustNot => "must_not",
+    }
+}
@@ -0,0 +1,94 @@
+use rustler::types::atom::Atom;
+use rustler::types::tuple::get_tuple;
+use rustler::Term;
+use tantivy::query::{BooleanQuery, Occur, Query};
+use tantivy::schema::{Field, Schema};
+use tantivy::Index;
+
+use super::atoms;
+use super::query::exists_query::ExistsQuery;
+use super::query::term_set_query::TermSetQuery;
+use super::tantex_error::TantexError;
+use super::terms::decode_single_term;
+use super::utils::fetch_field;
+
+pub enum Filter {
+    Exists(ExistsQuery),
+    Missing(ExistsQuery),
+    TermSet(Field, Vec<tantivy::Term>),
+}
+
+impl Filter {
+    fn into_clause(self) -> (Occur, Box<Query>) {
+        match self {
+            Filter::Exists(query) => (Occur::Must, Box::new(query)),
+            Filter::Missing(query) => (Occur::MustNot, Box::new(query)),
+            Filter::TermSet(field, terms) => {
+                (Occur::Must, Box::new(TermSetQuery::new(field, terms)))
+            }
+        }
+    }
+}
+
+pub fn decode_filters(
+    index: &Index,
+    schema: &Schema,
+    terms: Vec<Term>,
+) -> Result<Vec<Filter>, TantexError> {
+    let mut filters: Vec<Filter> = Vec::with_capacity(terms.len());
+    for term in terms {
+        filters.push(decode_filter(index, schema, term)?);
+    }
+    Ok(filters)
+}
+
+// Filters arrive as tagged tuples, e.g. `{:exists, "deleted_at"}` or
+// `{:in, "id", [1, 2, 3]}`.
+fn decode_filter(index: &Index, schema: &Schema, term: Term) -> Result<Filter, TantexError> {
+    let invalid = || TantexError::InvalidFilter(format!("{:?}", term));
+    let elements = get_tuple(term).map_err(|_| invalid())?;
+    let kind: Atom = match elements.first() {
+        Some(kind) => kind.decode().map_err(|_| invalid())?,
+        None => return Err(invalid()),
+    };
+    if kind == atoms::exists() && elements.len() == 2 {
+        let field = decode_field(schema, elements[1], &invalid)?;
+        Ok(Filter::Exists(ExistsQuery::for_field(schema, field)?))
+    } else if kind == atoms::missing() && elements.len() == 2 {
+        let field = decode_field(schema, elements[1], &invalid)?;
+        Ok(Filter::Missing(ExistsQuery::for_field(schema, field)?))
+    } else if kind == atoms::in_() && elements.len() == 3 {
+        let field = decode_field(schema, elements[1], &invalid)?;
+        let field_name = schema.get_field_name(field);
+        let values: Vec<Term> = elements[2].decode().map_err(|_| invalid())?;
+        let mut terms: Vec<tantivy::Term> = Vec::with_capacity(values.len());
+        for value in values {
+            terms.push(decode_single_term(index, schema, field_name, value)?);
+        }
+        Ok(Filter::TermSet(field, terms))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn decode_field<F>(schema: &Schema, term: Term, invalid: &F) -> Result<Field, TantexError>
+where
+    F: Fn() -> TantexError,
+{
+    let field_name: String = term.decode().map_err(|_| invalid())?;
+    fetch_field(schema, &field_name)
+}
+
+// Filter clauses score 0.0, so they narrow the hits without touching the
+// scores of `query`.
+pub fn apply_filters(query: Box<Query>, filters: Vec<Filter>) -> Box<Query> {
+    if filters.is_empty() {
+        return query;
+    }
+    let mut clauses: Vec<(Occur, Box<Query>)> = Vec::with_capacity(filters.len() + 1);
+    clauses.push((Occur::Must, query));
+    for filter in filters {
+        clauses.push(filter.into_clause());
+    }
+    Box::new(BooleanQuery::from(clauses))
+}
@@ -4,39 +4,93 @@ extern crate rustler;
 #[macro_use]
 extern crate lazy_static;
 
+extern crate fst_regex;
+extern crate num_cpus;
 extern crate rustler_codegen;
 extern crate tantivy;
 
+use std::time::Duration;
+
 use rustler::resource::ResourceArc;
+use rustler::schedule::SchedulerFlags;
+use rustler::types::map::map_new;
+use rustler::types::pid::Pid;
 use rustler::{Encoder, Env, NifResult, Term};
-use tantivy::schema::{Field, Type};
+use tantivy::query::Query;
 
+mod aggregation;
 mod atoms;
+mod collapse;
+mod document;
+mod explain;
+mod filter;
+mod parser_options;
+mod query;
+mod query_tree;
 mod schema;
+mod search_after;
+mod snapshot;
+mod snippet;
+mod stream;
 mod tantex_error;
+mod term_ords;
+mod terms;
 mod utils;
 mod wrapper;
 
+use aggregation::{decode_aggregations, Aggregation};
+use collapse::{fetch_collapse_key, CollapseCollector};
+use document::decode_document;
+use filter::{decode_filters, Filter};
+use parser_options::ParserOptions;
+use query_tree::encode_query;
 use schema::field_config::FieldConfig;
 use schema::schema_index::SchemaIndex;
+use search_after::{Cursor, Page};
+use snapshot::Snapshot;
+use snippet::{FieldSnippet, SnippetOptions};
+use stream::{spawn_stream, StreamHandle, StreamOptions};
 use tantex_error::TantexError;
+use terms::{build_term_query, decode_single_term, decode_terms};
 use wrapper::Wrapper;
 
 fn on_load<'a>(env: Env<'a>, _load_info: Term<'a>) -> bool {
     resource_struct_init!(Wrapper<SchemaIndex>, env);
+    resource_struct_init!(Wrapper<Snapshot>, env);
+    resource_struct_init!(Wrapper<StreamHandle>, env);
     true
 }
 
+// Opening and writing to the index wait on disk, and searches may run well
+// past the 1ms a NIF has on a normal scheduler, so those run on dirty IO and
+// dirty CPU schedulers. Pinning a snapshot, which stream_search also does,
+// may wait for a free searcher, and releasing one waits for the searches
+// still reading it, so those run on dirty IO schedulers too. Schema building
+// is done before the index opens, and query parsing and stream acks are
+// short, so those stay on normal schedulers.
 rustler_export_nifs! {
     "Elixir.Tantex.Native",
     [
         ("new_schema_index", 0, new_schema_index),
-        ("add_field", 5, add_field),
+        ("add_field", 6, add_field),
         ("finalize_schema", 1, finalize_schema),
-        ("open_index", 2, open_index),
-        ("write_documents", 3, write_documents),
-        ("limit_search", 4, limit_search),
-        ("find_one_by_term", 3, find_one_by_term),
+        ("open_index", 3, open_index, SchedulerFlags::DirtyIo),
+        ("write_documents", 3, write_documents, SchedulerFlags::DirtyIo),
+        ("limit_search", 10, limit_search, SchedulerFlags::DirtyCpu),
+        ("find_one_by_term", 4, find_one_by_term, SchedulerFlags::DirtyCpu),
+        ("find_all_by_term", 6, find_all_by_term, SchedulerFlags::DirtyCpu),
+        ("find_many_by_terms", 4, find_many_by_terms, SchedulerFlags::DirtyCpu),
+        ("regex_search", 5, regex_search, SchedulerFlags::DirtyCpu),
+        ("snippet_search", 11, snippet_search, SchedulerFlags::DirtyCpu),
+        ("explain", 6, explain, SchedulerFlags::DirtyCpu),
+        ("parse_only", 4, parse_only),
+        ("pin_snapshot", 1, pin_snapshot, SchedulerFlags::DirtyIo),
+        ("release_snapshot", 1, release_snapshot, SchedulerFlags::DirtyIo),
+        ("stream_search", 9, stream_search, SchedulerFlags::DirtyIo),
+        ("ack_stream", 1, ack_stream),
+        ("cancel_stream", 1, cancel_stream),
+        ("aggregate", 6, aggregate, SchedulerFlags::DirtyCpu),
+        ("collapse_search", 9, collapse_search, SchedulerFlags::DirtyCpu),
     ],
     Some(on_load)
 }
@@ -54,14 +108,15 @@ fn add_field<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let kind: String = args[2].decode()?;
     let stored: bool = args[3].decode()?;
     let fast: bool = args[4].decode()?;
+    let multi: bool = args[5].decode()?;
     let mut schema_index = schema_index_wrapper.lock.write().unwrap();
     let field_config = match FieldConfig::build(&kind, stored, fast) {
         Ok(f) => f,
-        Err(e) => return Ok((atoms::error(), e.to_reason()).encode(env)),
+        Err(e) => return Ok((atoms::error(), e.to_reason(env)).encode(env)),
     };
-    match schema_index.add_field(&field_name, field_config) {
+    match schema_index.add_field(&field_name, field_config, multi) {
         Ok(_) => Ok(atoms::ok().encode(env)),
-        Err(e) => Ok((atoms::error(), e.to_reason()).encode(env)),
+        Err(e) => Ok((atoms::error(), e.to_reason(env)).encode(env)),
     }
 }
 
@@ -70,18 +125,19 @@ fn finalize_schema<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let mut schema_index = schema_index_wrapper.lock.write().unwrap();
     match schema_index.finalize_schema() {
         Ok(()) => Ok(atoms::ok().encode(env)),
-        Err(e) => Ok((atoms::error(), e.to_reason()).encode(env)),
+        Err(e) => Ok((atoms::error(), e.to_reason(env)).encode(env)),
     }
 }
 
 fn open_index<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
     let index_path: String = args[1].decode()?;
+    let max_snapshots: usize = args[2].decode()?;
     let mut schema_index = schema_index_wrapper.lock.write().unwrap();
 
-    match schema_index.open_index(&index_path) {
+    match schema_index.open_index(&index_path, max_snapshots) {
         Ok(()) => Ok(atoms::ok().encode(env)),
-        Err(e) => Ok((atoms::error(), e.to_reason()).encode(env)),
+        Err(e) => Ok((atoms::error(), e.to_reason(env)).encode(env)),
     }
 }
 
@@ -90,23 +146,315 @@ fn limit_search<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let field_strings: Vec<String> = args[1].decode()?;
     let pattern: String = args[2].decode()?;
     let limit: usize = args[3].decode()?;
+    let return_fields: Vec<String> = args[4].decode()?;
+    let filter_terms: Vec<Term<'a>> = args[5].decode()?;
+    let parser_options = decode_parser_options(args[6])?;
+    let aggregation_terms: Vec<Term<'a>> = args[7].decode()?;
+    let cursor: Option<String> = args[8].decode()?;
+    let snapshot_wrapper: Option<ResourceArc<Wrapper<Snapshot>>> = args[9].decode()?;
     let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let snapshot = snapshot_wrapper
+        .as_ref()
+        .map(|wrapper| wrapper.lock.read().unwrap());
     // let index = index_wrapper.lock.read().unwrap();
-    match schema_index.limit_search(field_strings, &pattern, limit) {
-        Ok(docs) => Ok((atoms::ok(), docs).encode(env)),
-        Err(e) => Ok((atoms::error(), e.to_reason()).encode(env)),
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    let filters = match fetch_filters(&schema_index, filter_terms) {
+        Ok(filters) => filters,
+        Err(e) => return render_error(env, e),
+    };
+    let aggregations = match fetch_aggregations(&schema_index, aggregation_terms) {
+        Ok(aggregations) => aggregations,
+        Err(e) => return render_error(env, e),
+    };
+    let after = match cursor.map(|cursor| Cursor::parse(&cursor)) {
+        Some(Ok(after)) => Some(after),
+        Some(Err(e)) => return render_error(env, e),
+        None => None,
+    };
+    let page = Page { limit, after };
+    match schema_index.limit_search(
+        field_strings,
+        &pattern,
+        page,
+        filters,
+        &parser_options,
+        aggregations,
+        snapshot.as_ref().map(|snapshot| &**snapshot),
+    ) {
+        Ok((docs, results, last)) => {
+            let docs = match encoder.encode_all(env, &docs) {
+                Ok(docs) => docs,
+                Err(e) => return render_error(env, e),
+            };
+            let cursor = last.map(|cursor| cursor.to_string());
+            Ok((atoms::ok(), docs, results, cursor).encode(env))
+        }
+        Err(e) => Ok((atoms::error(), e.to_reason(env)).encode(env)),
+    }
+}
+
+fn snippet_search<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let limit: usize = args[3].decode()?;
+    let snippet_options = SnippetOptions {
+        fields: args[4].decode()?,
+        pre_tag: args[5].decode()?,
+        post_tag: args[6].decode()?,
+        max_num_chars: args[7].decode()?,
+    };
+    let return_fields: Vec<String> = args[8].decode()?;
+    let filter_terms: Vec<Term<'a>> = args[9].decode()?;
+    let parser_options = decode_parser_options(args[10])?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    let filters = match fetch_filters(&schema_index, filter_terms) {
+        Ok(filters) => filters,
+        Err(e) => return render_error(env, e),
+    };
+    let found = schema_index.snippet_search(
+        field_strings,
+        &pattern,
+        limit,
+        filters,
+        &parser_options,
+        &snippet_options,
+    );
+    match found {
+        Ok(results) => {
+            let mut encoded: Vec<(Term<'a>, Vec<FieldSnippet>)> = Vec::with_capacity(results.len());
+            for (doc, snippets) in results {
+                match encoder.encode(env, &doc) {
+                    Ok(doc) => encoded.push((doc, snippets)),
+                    Err(e) => return render_error(env, e),
+                }
+            }
+            Ok((atoms::ok(), encoded).encode(env))
+        }
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn regex_search<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_name: String = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let limit: usize = args[3].decode()?;
+    let size_limit: usize = args[4].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let encoder = match schema_index.document_encoder(Vec::new()) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.regex_search(&field_name, &pattern, limit, size_limit) {
+        Ok(docs) => match encoder.encode_all(env, &docs) {
+            Ok(docs) => Ok((atoms::ok(), docs).encode(env)),
+            Err(e) => render_error(env, e),
+        },
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn explain<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let parser_options = decode_parser_options(args[3])?;
+    let key_field: String = args[4].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let key = match fetch_single_term(&schema_index, &key_field, args[5]) {
+        Ok(key) => key,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.explain(field_strings, &pattern, &parser_options, &key) {
+        Ok(explanation) => Ok((atoms::ok(), explanation).encode(env)),
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn aggregate<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let filter_terms: Vec<Term<'a>> = args[3].decode()?;
+    let parser_options = decode_parser_options(args[4])?;
+    let aggregation_terms: Vec<Term<'a>> = args[5].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let filters = match fetch_filters(&schema_index, filter_terms) {
+        Ok(filters) => filters,
+        Err(e) => return render_error(env, e),
+    };
+    let aggregations = match fetch_aggregations(&schema_index, aggregation_terms) {
+        Ok(aggregations) => aggregations,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.aggregate(
+        field_strings,
+        &pattern,
+        filters,
+        &parser_options,
+        aggregations,
+    ) {
+        Ok(results) => Ok((atoms::ok(), results).encode(env)),
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn collapse_search<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let filter_terms: Vec<Term<'a>> = args[3].decode()?;
+    let parser_options = decode_parser_options(args[4])?;
+    let collapse_field: String = args[5].decode()?;
+    let groups: usize = args[6].decode()?;
+    let per_group: usize = args[7].decode()?;
+    let return_fields: Vec<String> = args[8].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    let filters = match fetch_filters(&schema_index, filter_terms) {
+        Ok(filters) => filters,
+        Err(e) => return render_error(env, e),
+    };
+    let collapse = match fetch_collapse(&schema_index, &collapse_field, groups, per_group) {
+        Ok(collapse) => collapse,
+        Err(e) => return render_error(env, e),
+    };
+    let found =
+        schema_index.collapse_search(field_strings, &pattern, filters, &parser_options, collapse);
+    match found {
+        Ok(results) => {
+            let mut encoded: Vec<(Term<'a>, u64, Vec<Term<'a>>)> =
+                Vec::with_capacity(results.len());
+            for (value, hit_count, docs) in results {
+                match encoder.encode_all(env, &docs) {
+                    Ok(docs) => encoded.push((value.encode(env), hit_count, docs)),
+                    Err(e) => return render_error(env, e),
+                }
+            }
+            Ok((atoms::ok(), encoded).encode(env))
+        }
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn pin_snapshot<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    match schema_index.pin_snapshot() {
+        Ok(snapshot) => {
+            let resource: ResourceArc<Wrapper<Snapshot>> = ResourceArc::new(Wrapper::new(snapshot));
+            Ok((atoms::ok(), resource).encode(env))
+        }
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn release_snapshot<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let snapshot_wrapper: ResourceArc<Wrapper<Snapshot>> = args[0].decode()?;
+    let mut snapshot = snapshot_wrapper.lock.write().unwrap();
+    snapshot.release();
+    Ok(atoms::ok().encode(env))
+}
+
+fn stream_search<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let filter_terms: Vec<Term<'a>> = args[3].decode()?;
+    let parser_options = decode_parser_options(args[4])?;
+    let return_fields: Vec<String> = args[5].decode()?;
+    let options = StreamOptions {
+        chunk_size: args[6].decode()?,
+        timeout: Duration::from_millis(args[7].decode()?),
+    };
+    let pid: Pid = args[8].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let filters = match fetch_filters(&schema_index, filter_terms) {
+        Ok(filters) => filters,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.stream_request(
+        field_strings,
+        &pattern,
+        filters,
+        parser_options,
+        return_fields,
+        options,
+    ) {
+        Ok(request) => {
+            let (stream_id, handle) = spawn_stream(pid, request);
+            let resource: ResourceArc<Wrapper<StreamHandle>> =
+                ResourceArc::new(Wrapper::new(handle));
+            Ok((atoms::ok(), stream_id, resource).encode(env))
+        }
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn ack_stream<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let handle_wrapper: ResourceArc<Wrapper<StreamHandle>> = args[0].decode()?;
+    let handle = handle_wrapper.lock.read().unwrap();
+    handle.ack();
+    Ok(atoms::ok().encode(env))
+}
+
+fn cancel_stream<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let handle_wrapper: ResourceArc<Wrapper<StreamHandle>> = args[0].decode()?;
+    let handle = handle_wrapper.lock.read().unwrap();
+    handle.cancel();
+    Ok(atoms::ok().encode(env))
+}
+
+fn parse_only<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let field_strings: Vec<String> = args[1].decode()?;
+    let pattern: String = args[2].decode()?;
+    let parser_options = decode_parser_options(args[3])?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let schema = match schema_index.fetch_schema() {
+        Ok(schema) => schema,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.parse_only(field_strings, &pattern, &parser_options) {
+        Ok(query) => {
+            let tree = encode_query(env, schema, &*query);
+            Ok((atoms::ok(), tree, format!("{:?}", query)).encode(env))
+        }
+        Err(e) => render_error(env, e),
     }
 }
 
 fn write_documents<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
     let schema_index = schema_index_wrapper.lock.read().unwrap();
-    let json_docs: Vec<String> = args[1].decode()?;
+    let terms: Vec<Term<'a>> = args[1].decode()?;
     let heap_size: usize = args[2].decode()?;
+    let schema = match schema_index.fetch_schema() {
+        Ok(schema) => schema,
+        Err(e) => return render_error(env, e),
+    };
+    let mut docs = Vec::with_capacity(terms.len());
+    for term in terms {
+        match decode_document(schema, schema_index.multi_valued(), term) {
+            Ok(doc) => docs.push(doc),
+            Err(e) => return render_error(env, e),
+        }
+    }
 
-    match schema_index.write_documents(json_docs, heap_size) {
+    match schema_index.write_documents(docs, heap_size) {
         Ok(last) => Ok((atoms::ok(), last).encode(env)),
-        Err(e) => Ok((atoms::error(), e.to_reason()).encode(env)),
+        Err(e) => Ok((atoms::error(), e.to_reason(env)).encode(env)),
     }
 }
 
@@ -114,51 +462,140 @@ fn find_one_by_term<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>>
     let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
     let schema_index = schema_index_wrapper.lock.read().unwrap();
     let field_name: String = args[1].decode()?;
-    let field: Field = match schema_index.fetch_field(&field_name) {
+    let return_fields: Vec<String> = args[3].decode()?;
+    let query = match fetch_term_query(&schema_index, &field_name, args[2]) {
+        Ok(query) => query,
         Err(e) => return render_error(env, e),
-        Ok(field) => field,
     };
-    let term: tantivy::Term = match schema_index.fetch_field_type(&field_name) {
-        Ok(Type::I64) => {
-            let val: i64 = match args[2].decode() {
-                Ok(v) => v,
-                Err(_) => return bad_data(env, Type::I64, field_name),
-            };
-            tantivy::Term::from_field_i64(field, val)
-        }
-        Ok(Type::U64) => {
-            let val: u64 = match args[2].decode() {
-                Ok(v) => v,
-                Err(_) => return bad_data(env, Type::U64, field_name),
-            };
-            tantivy::Term::from_field_u64(field, val)
-        }
-        Ok(Type::Str) => {
-            let val: String = match args[2].decode() {
-                Ok(v) => v,
-                Err(_) => return bad_data(env, Type::Str, field_name),
-            };
-            tantivy::Term::from_field_text(field, &val)
-        }
-        Ok(t) => {
-            let e = TantexError::TypeCannotBeSearched(t);
-            return render_error(env, e);
-        }
-        Err(e) => {
-            return render_error(env, e);
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.fetch_one_by_query(&*query) {
+        Ok(doc) => match encoder.encode(env, &doc) {
+            Ok(doc) => Ok((atoms::ok(), doc).encode(env)),
+            Err(e) => render_error(env, e),
+        },
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn find_all_by_term<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let field_name: String = args[1].decode()?;
+    let limit: usize = args[3].decode()?;
+    let offset: usize = args[4].decode()?;
+    let return_fields: Vec<String> = args[5].decode()?;
+    let query = match fetch_term_query(&schema_index, &field_name, args[2]) {
+        Ok(query) => query,
+        Err(e) => return render_error(env, e),
+    };
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
+    };
+    match schema_index.fetch_all_by_query(&*query, limit, offset) {
+        Ok((docs, total)) => match encoder.encode_all(env, &docs) {
+            Ok(docs) => Ok((atoms::ok(), docs, total).encode(env)),
+            Err(e) => render_error(env, e),
+        },
+        Err(e) => render_error(env, e),
+    }
+}
+
+fn find_many_by_terms<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let schema_index_wrapper: ResourceArc<Wrapper<SchemaIndex>> = args[0].decode()?;
+    let schema_index = schema_index_wrapper.lock.read().unwrap();
+    let field_name: String = args[1].decode()?;
+    let keys: Vec<Term<'a>> = args[2].decode()?;
+    let return_fields: Vec<String> = args[3].decode()?;
+    let mut terms: Vec<tantivy::Term> = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        match fetch_single_term(&schema_index, &field_name, *key) {
+            Ok(term) => terms.push(term),
+            Err(e) => return render_error(env, e),
         }
+    }
+    let encoder = match schema_index.document_encoder(return_fields) {
+        Ok(encoder) => encoder,
+        Err(e) => return render_error(env, e),
     };
-    match schema_index.fetch_one_by_term(term) {
-        Ok(json_doc) => Ok((atoms::ok(), json_doc).encode(env)),
+    let docs = match schema_index.fetch_many_by_terms(&terms) {
+        Ok(docs) => docs,
         Err(e) => return render_error(env, e),
+    };
+    let mut found = map_new(env);
+    for (key, doc) in keys.into_iter().zip(docs.iter()) {
+        let value = match doc {
+            Some(doc) => match encoder.encode(env, doc) {
+                Ok(doc) => doc,
+                Err(e) => return render_error(env, e),
+            },
+            None => atoms::not_found().encode(env),
+        };
+        found = found.map_put(key, value)?;
     }
+    Ok((atoms::ok(), found).encode(env))
 }
 
-fn render_error<'a>(env: Env<'a>, e: TantexError) -> NifResult<Term<'a>> {
-    Ok((atoms::error(), e.to_reason()).encode(env))
+fn fetch_term_query(
+    schema_index: &SchemaIndex,
+    field_name: &str,
+    value: Term,
+) -> Result<Box<Query>, TantexError> {
+    let index = schema_index.fetch_index()?;
+    let schema = schema_index.fetch_schema()?;
+    let terms = decode_terms(index, schema, field_name, value)?;
+    build_term_query(schema, field_name, terms)
+}
+
+fn fetch_single_term(
+    schema_index: &SchemaIndex,
+    field_name: &str,
+    value: Term,
+) -> Result<tantivy::Term, TantexError> {
+    let index = schema_index.fetch_index()?;
+    let schema = schema_index.fetch_schema()?;
+    decode_single_term(index, schema, field_name, value)
 }
 
-fn bad_data<'a>(env: Env<'a>, t: Type, field_name: String) -> NifResult<Term<'a>> {
-    let e = TantexError::InvalidFieldData(t, field_name);
-    render_error(env, e)
+fn fetch_filters(schema_index: &SchemaIndex, terms: Vec<Term>) -> Result<Vec<Filter>, TantexError> {
+    let index = schema_index.fetch_index()?;
+    let schema = schema_index.fetch_schema()?;
+    decode_filters(index, schema, terms)
+}
+
+fn fetch_aggregations(
+    schema_index: &SchemaIndex,
+    terms: Vec<Term>,
+) -> Result<Vec<Aggregation>, TantexError> {
+    let schema = schema_index.fetch_schema()?;
+    decode_aggregations(schema, terms)
+}
+
+fn fetch_collapse(
+    schema_index: &SchemaIndex,
+    field_name: &str,
+    groups: usize,
+    per_group: usize,
+) -> Result<CollapseCollector, TantexError> {
+    let schema = schema_index.fetch_schema()?;
+    let key = fetch_collapse_key(schema, field_name)?;
+    Ok(CollapseCollector::new(key, groups, per_group))
+}
+
+fn decode_parser_options(term: Term) -> NifResult<ParserOptions> {
+    let (conjunction, boosts): (bool, Vec<(String, f64)>) = term.decode()?;
+    Ok(ParserOptions {
+        conjunction,
+        boosts: boosts
+            .into_iter()
+            .map(|(field_name, boost)| (field_name, boost as f32))
+            .collect(),
+    })
+}
+
+fn render_error<'a>(env: Env<'a>, e: TantexError) -> NifResult<Term<'a>> {
+    Ok((atoms::error(), e.to_reason(env)).encode(env))
 }
@@ -0,0 +1,61 @@
+use tantivy::query::{BooleanQuery, Occur, PhraseQuery, Query, QueryParser, TermQuery};
+use tantivy::schema::{Field, Schema};
+use tantivy::{Index, Score};
+
+use super::query::boost_query::BoostQuery;
+use super::tantex_error::TantexError;
+use super::utils::{fetch_field, parse_query};
+
+pub struct ParserOptions {
+    pub conjunction: bool,
+    pub boosts: Vec<(String, Score)>,
+}
+
+pub fn build_query(
+    index: &Index,
+    schema: &Schema,
+    fields: Vec<Field>,
+    pattern: &str,
+    options: &ParserOptions,
+) -> Result<Box<Query>, TantexError> {
+    let mut query_parser = QueryParser::for_index(index, fields);
+    if options.conjunction {
+        query_parser.set_conjunction_by_default();
+    }
+    let query = parse_query(&query_parser, pattern)?;
+    if options.boosts.is_empty() {
+        return Ok(query);
+    }
+    let mut boosts: Vec<(Field, Score)> = Vec::with_capacity(options.boosts.len());
+    for (field_name, boost) in options.boosts.iter() {
+        boosts.push((fetch_field(schema, field_name)?, *boost));
+    }
+    Ok(boost_fields(&*query, &boosts))
+}
+
+// tantivy's QueryParser has no notion of field boosts, so the parsed query is
+// rebuilt with every term and phrase on a boosted field wrapped in a
+// BoostQuery. Everything else, including the clause structure the parser
+// chose, is kept as is.
+fn boost_fields(query: &Query, boosts: &[(Field, Score)]) -> Box<Query> {
+    if let Ok(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+        let clauses: Vec<(Occur, Box<Query>)> = boolean_query
+            .clauses()
+            .iter()
+            .map(|(occur, clause)| (*occur, boost_fields(&**clause, boosts)))
+            .collect();
+        return Box::new(BooleanQuery::from(clauses));
+    }
+    let field = if let Ok(term_query) = query.downcast_ref::<TermQuery>() {
+        Some(term_query.term().field())
+    } else if let Ok(phrase_query) = query.downcast_ref::<PhraseQuery>() {
+        phrase_query.phrase_terms().first().map(|term| term.field())
+    } else {
+        None
+    };
+    let boost = field.and_then(|field| boosts.iter().find(|(boosted, _)| *boosted == field));
+    match boost {
+        Some((_, boost)) => Box::new(BoostQuery::new(query.box_clone(), *boost)),
+        None => query.box_clone(),
+    }
+}
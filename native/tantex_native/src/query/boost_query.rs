@@ -0,0 +1,92 @@
+use std::collections::BTreeSet;
+
+use tantivy::query::{Query, Scorer, Weight};
+use tantivy::{DocId, DocSet, Result, Score, Searcher, SegmentReader, SkipResult, Term};
+
+// Multiplies every score produced by `query` by `boost`.
+#[derive(Debug)]
+pub struct BoostQuery {
+    query: Box<Query>,
+    boost: Score,
+}
+
+impl BoostQuery {
+    pub fn new(query: Box<Query>, boost: Score) -> BoostQuery {
+        BoostQuery { query, boost }
+    }
+
+    pub fn query(&self) -> &Query {
+        &*self.query
+    }
+
+    pub fn boost(&self) -> Score {
+        self.boost
+    }
+}
+
+impl Clone for BoostQuery {
+    fn clone(&self) -> BoostQuery {
+        BoostQuery {
+            query: self.query.box_clone(),
+            boost: self.boost,
+        }
+    }
+}
+
+impl Query for BoostQuery {
+    fn weight(&self, searcher: &Searcher, scoring_enabled: bool) -> Result<Box<Weight>> {
+        let weight = self.query.weight(searcher, scoring_enabled)?;
+        Ok(Box::new(BoostWeight {
+            weight,
+            boost: self.boost,
+        }))
+    }
+
+    fn query_terms(&self, term_set: &mut BTreeSet<Term>) {
+        self.query.query_terms(term_set)
+    }
+}
+
+struct BoostWeight {
+    weight: Box<Weight>,
+    boost: Score,
+}
+
+impl Weight for BoostWeight {
+    fn scorer(&self, reader: &SegmentReader) -> Result<Box<Scorer>> {
+        let scorer = self.weight.scorer(reader)?;
+        Ok(Box::new(BoostScorer {
+            scorer,
+            boost: self.boost,
+        }))
+    }
+}
+
+struct BoostScorer {
+    scorer: Box<Scorer>,
+    boost: Score,
+}
+
+impl DocSet for BoostScorer {
+    fn advance(&mut self) -> bool {
+        self.scorer.advance()
+    }
+
+    fn skip_next(&mut self, target: DocId) -> SkipResult {
+        self.scorer.skip_next(target)
+    }
+
+    fn doc(&self) -> DocId {
+        self.scorer.doc()
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.scorer.size_hint()
+    }
+}
+
+impl Scorer for BoostScorer {
+    fn score(&mut self) -> Score {
+        self.scorer.score() * self.boost
+    }
+}
@@ -0,0 +1,54 @@
+use tantivy::query::Scorer;
+use tantivy::{DocId, DocSet, Score};
+
+// A scorer over a sorted list of doc ids that gives every hit the same score.
+pub struct DocIdSetScorer {
+    docs: Vec<DocId>,
+    next: usize,
+    score: Score,
+}
+
+impl DocIdSetScorer {
+    pub fn new(docs: Vec<DocId>, score: Score) -> DocIdSetScorer {
+        DocIdSetScorer {
+            docs,
+            next: 0,
+            score,
+        }
+    }
+
+    pub fn from_bitset(bitset: &[bool], score: Score) -> DocIdSetScorer {
+        let docs: Vec<DocId> = bitset
+            .iter()
+            .enumerate()
+            .filter(|&(_, &present)| present)
+            .map(|(doc, _)| doc as DocId)
+            .collect();
+        DocIdSetScorer::new(docs, score)
+    }
+}
+
+impl DocSet for DocIdSetScorer {
+    fn advance(&mut self) -> bool {
+        if self.next < self.docs.len() {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn doc(&self) -> DocId {
+        self.docs[self.next - 1]
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.docs.len() as u32
+    }
+}
+
+impl Scorer for DocIdSetScorer {
+    fn score(&mut self) -> Score {
+        self.score
+    }
+}
@@ -0,0 +1,189 @@
+use tantivy::fastfield::FastFieldReader;
+use tantivy::postings::SegmentPostings;
+use tantivy::query::{Query, Scorer, Weight};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema};
+use tantivy::{DocId, DocSet, Result, Score, Searcher, SegmentReader, Term};
+
+use super::super::tantex_error::TantexError;
+use super::doc_id_set::DocIdSetScorer;
+
+// How a segment tells which documents hold a value for the field.
+#[derive(Clone, Copy, Debug)]
+enum Presence {
+    // Every posting of every term of the field is walked.
+    Postings,
+    // A single-valued fast field stores 0 for documents that never had a
+    // value, so a non-zero value proves presence and the postings of the
+    // term 0 settle the rest.
+    FastU64,
+    FastI64,
+}
+
+// Matches every document that holds at least one value for `field`.
+#[derive(Clone, Debug)]
+pub struct ExistsQuery {
+    field: Field,
+    presence: Presence,
+}
+
+impl ExistsQuery {
+    // Fails for fields that are not indexed, since nothing then records
+    // which documents hold a value.
+    pub fn for_field(
+        schema: &Schema,
+        field: Field,
+    ) -> std::result::Result<ExistsQuery, TantexError> {
+        let entry = schema.get_field_entry(field);
+        let presence = match entry.field_type() {
+            FieldType::U64(options) if options.is_indexed() && options.is_fast() => {
+                Presence::FastU64
+            }
+            FieldType::I64(options) if options.is_indexed() && options.is_fast() => {
+                Presence::FastI64
+            }
+            FieldType::U64(options) | FieldType::I64(options) if options.is_indexed() => {
+                Presence::Postings
+            }
+            FieldType::Str(options) if options.get_indexing_options().is_some() => {
+                Presence::Postings
+            }
+            FieldType::HierarchicalFacet => Presence::Postings,
+            _ => return Err(TantexError::FieldNotIndexed(entry.name().to_string())),
+        };
+        Ok(ExistsQuery { field, presence })
+    }
+}
+
+impl Query for ExistsQuery {
+    fn weight(&self, _searcher: &Searcher, _scoring_enabled: bool) -> Result<Box<Weight>> {
+        Ok(Box::new(ExistsWeight {
+            field: self.field,
+            presence: self.presence,
+        }))
+    }
+}
+
+struct ExistsWeight {
+    field: Field,
+    presence: Presence,
+}
+
+impl Weight for ExistsWeight {
+    fn scorer(&self, reader: &SegmentReader) -> Result<Box<Scorer>> {
+        match self.presence {
+            Presence::Postings => Ok(Box::new(self.postings_scorer(reader))),
+            Presence::FastU64 => {
+                let values = FastValues::U64(reader.fast_field_reader::<u64>(self.field)?);
+                let zero = Term::from_field_u64(self.field, 0);
+                Ok(Box::new(FastFieldExistsScorer::new(reader, values, &zero)))
+            }
+            Presence::FastI64 => {
+                let values = FastValues::I64(reader.fast_field_reader::<i64>(self.field)?);
+                let zero = Term::from_field_i64(self.field, 0);
+                Ok(Box::new(FastFieldExistsScorer::new(reader, values, &zero)))
+            }
+        }
+    }
+}
+
+impl ExistsWeight {
+    fn postings_scorer(&self, reader: &SegmentReader) -> DocIdSetScorer {
+        let inverted_index = reader.inverted_index(self.field);
+        let mut present = vec![false; reader.max_doc() as usize];
+        let mut term_stream = inverted_index.terms().stream();
+        while term_stream.advance() {
+            let term_info = term_stream.value();
+            let mut postings =
+                inverted_index.read_postings_from_terminfo(term_info, IndexRecordOption::Basic);
+            while postings.advance() {
+                present[postings.doc() as usize] = true;
+            }
+        }
+        DocIdSetScorer::from_bitset(&present, 0.0)
+    }
+}
+
+enum FastValues {
+    U64(FastFieldReader<u64>),
+    I64(FastFieldReader<i64>),
+}
+
+impl FastValues {
+    fn is_zero(&self, doc: DocId) -> bool {
+        match self {
+            FastValues::U64(reader) => reader.get(doc) == 0,
+            FastValues::I64(reader) => reader.get(doc) == 0,
+        }
+    }
+}
+
+// Walks the segment's doc ids in order without allocating per document:
+// the fast field answers for every non-zero value and the postings of the
+// term 0 are read alongside for the others.
+struct FastFieldExistsScorer {
+    values: FastValues,
+    zeros: Option<SegmentPostings>,
+    max_doc: DocId,
+    next: DocId,
+    doc: DocId,
+}
+
+impl FastFieldExistsScorer {
+    fn new(reader: &SegmentReader, values: FastValues, zero: &Term) -> FastFieldExistsScorer {
+        let mut zeros = reader
+            .inverted_index(zero.field())
+            .read_postings(zero, IndexRecordOption::Basic);
+        if let Some(false) = zeros.as_mut().map(|postings| postings.advance()) {
+            zeros = None;
+        }
+        FastFieldExistsScorer {
+            values,
+            zeros,
+            max_doc: reader.max_doc(),
+            next: 0,
+            doc: 0,
+        }
+    }
+
+    // The zero postings only ever move forward, as doc ids are asked for in
+    // order.
+    fn holds_zero(&mut self, doc: DocId) -> bool {
+        while let Some(postings) = self.zeros.as_mut() {
+            if postings.doc() >= doc {
+                return postings.doc() == doc;
+            }
+            if !postings.advance() {
+                self.zeros = None;
+            }
+        }
+        false
+    }
+}
+
+impl DocSet for FastFieldExistsScorer {
+    fn advance(&mut self) -> bool {
+        while self.next < self.max_doc {
+            let doc = self.next;
+            self.next += 1;
+            if !self.values.is_zero(doc) || self.holds_zero(doc) {
+                self.doc = doc;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.max_doc
+    }
+}
+
+impl Scorer for FastFieldExistsScorer {
+    fn score(&mut self) -> Score {
+        0.0
+    }
+}
@@ -0,0 +1,5 @@
+pub mod boost_query;
+pub mod doc_id_set;
+pub mod exists_query;
+pub mod regex_query;
+pub mod term_set_query;
@@ -0,0 +1,33 @@
+use fst_regex::Regex;
+use tantivy::query::{AutomatonWeight, Query, Weight};
+use tantivy::schema::Field;
+use tantivy::{Result, Searcher, TantivyError};
+
+// Matches the terms of `field` accepted by the regex, like tantivy's
+// RegexQuery, except that the automaton is compiled with `size_limit` rather
+// than fst-regex's default, so the caller's limit bounds the automaton that
+// actually walks the term dictionary.
+#[derive(Clone, Debug)]
+pub struct SizeLimitedRegexQuery {
+    field: Field,
+    pattern: String,
+    size_limit: usize,
+}
+
+impl SizeLimitedRegexQuery {
+    pub fn new(field: Field, pattern: String, size_limit: usize) -> SizeLimitedRegexQuery {
+        SizeLimitedRegexQuery {
+            field,
+            pattern,
+            size_limit,
+        }
+    }
+}
+
+impl Query for SizeLimitedRegexQuery {
+    fn weight(&self, _searcher: &Searcher, _scoring_enabled: bool) -> Result<Box<Weight>> {
+        let automaton = Regex::with_size_limit(self.size_limit, &self.pattern)
+            .map_err(|e| TantivyError::InvalidArgument(format!("{}", e)))?;
+        Ok(Box::new(AutomatonWeight::new(self.field, automaton)))
+    }
+}
@@ -0,0 +1,52 @@
+use tantivy::query::{Query, Scorer, Weight};
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::{DocSet, Result, Searcher, SegmentReader, Term};
+
+use super::doc_id_set::DocIdSetScorer;
+
+// Matches every document holding any of `terms` in `field`. The postings of
+// each term are unioned per segment, which stays cheap for thousands of terms
+// where an OR-ed BooleanQuery would build one scorer per term.
+#[derive(Clone, Debug)]
+pub struct TermSetQuery {
+    field: Field,
+    terms: Vec<Term>,
+}
+
+impl TermSetQuery {
+    pub fn new(field: Field, mut terms: Vec<Term>) -> TermSetQuery {
+        terms.sort();
+        terms.dedup();
+        TermSetQuery { field, terms }
+    }
+}
+
+impl Query for TermSetQuery {
+    fn weight(&self, _searcher: &Searcher, _scoring_enabled: bool) -> Result<Box<Weight>> {
+        Ok(Box::new(TermSetWeight {
+            field: self.field,
+            terms: self.terms.clone(),
+        }))
+    }
+}
+
+struct TermSetWeight {
+    field: Field,
+    terms: Vec<Term>,
+}
+
+impl Weight for TermSetWeight {
+    fn scorer(&self, reader: &SegmentReader) -> Result<Box<Scorer>> {
+        let inverted_index = reader.inverted_index(self.field);
+        let mut present = vec![false; reader.max_doc() as usize];
+        for term in self.terms.iter() {
+            if let Some(mut postings) = inverted_index.read_postings(term, IndexRecordOption::Basic)
+            {
+                while postings.advance() {
+                    present[postings.doc() as usize] = true;
+                }
+            }
+        }
+        Ok(Box::new(DocIdSetScorer::from_bitset(&present, 0.0)))
+    }
+}
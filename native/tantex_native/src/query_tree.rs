@@ -0,0 +1,80 @@
+use rustler::{Encoder, Env, Term};
+use tantivy::query::{AllQuery, BooleanQuery, Occur, PhraseQuery, Query, RangeQuery, TermQuery};
+use tantivy::schema::{FieldType, Schema};
+
+use super::atoms;
+use super::query::boost_query::BoostQuery;
+
+// Renders a parsed query as nested tuples:
+//
+//   {:boolean, [{:must | :should | :must_not, query}]}
+//   {:term, field, value}
+//   {:phrase, field, [value]}
+//   {:range, field, debug}
+//   {:boost, boost, query}
+//   :all
+//   {:other, debug}
+//
+// tantivy 0.8 keeps the bounds of a RangeQuery private, so ranges only carry
+// their Debug output.
+pub fn encode_query<'a>(env: Env<'a>, schema: &Schema, query: &Query) -> Term<'a> {
+    if let Ok(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+        let clauses: Vec<Term<'a>> = boolean_query
+            .clauses()
+            .iter()
+            .map(|(occur, clause)| {
+                let subquery = encode_query(env, schema, &**clause);
+                (encode_occur(env, *occur), subquery).encode(env)
+            })
+            .collect();
+        (atoms::boolean(), clauses).encode(env)
+    } else if let Ok(term_query) = query.downcast_ref::<TermQuery>() {
+        let term = term_query.term();
+        let field_name = schema.get_field_name(term.field());
+        (
+            atoms::term(),
+            field_name,
+            encode_term_value(env, schema, term),
+        )
+            .encode(env)
+    } else if let Ok(phrase_query) = query.downcast_ref::<PhraseQuery>() {
+        let terms = phrase_query.phrase_terms();
+        let field_name = match terms.first() {
+            Some(term) => schema.get_field_name(term.field()),
+            None => "",
+        };
+        let values: Vec<Term<'a>> = terms
+            .iter()
+            .map(|term| encode_term_value(env, schema, term))
+            .collect();
+        (atoms::phrase(), field_name, values).encode(env)
+    } else if let Ok(range_query) = query.downcast_ref::<RangeQuery>() {
+        let field_name = schema.get_field_name(range_query.field());
+        let debug = format!("{:?}", range_query);
+        (atoms::range(), field_name, debug).encode(env)
+    } else if let Ok(boost_query) = query.downcast_ref::<BoostQuery>() {
+        let subquery = encode_query(env, schema, boost_query.query());
+        (atoms::boost(), boost_query.boost(), subquery).encode(env)
+    } else if query.downcast_ref::<AllQuery>().is_ok() {
+        atoms::all().encode(env)
+    } else {
+        (atoms::other(), format!("{:?}", query)).encode(env)
+    }
+}
+
+fn encode_occur<'a>(env: Env<'a>, occur: Occur) -> Term<'a> {
+    match occur {
+        Occur::Must => atoms::must().encode(env),
+        Occur::Should => atoms::should().encode(env),
+        Occur::MustNot => atoms::must_not().encode(env),
+    }
+}
+
+fn encode_term_value<'a>(env: Env<'a>, schema: &Schema, term: &tantivy::Term) -> Term<'a> {
+    match schema.get_field_entry(term.field()).field_type() {
+        FieldType::Str(_) => term.text().encode(env),
+        FieldType::U64(_) => term.get_u64().encode(env),
+        FieldType::I64(_) => term.get_i64().encode(env),
+        _ => format!("{:?}", term).encode(env),
+    }
+}
@@ -1,9 +1,27 @@
-use tantivy::query::{QueryParser, TermQuery};
-use tantivy::schema::{Field, IndexRecordOption, Schema, SchemaBuilder, Type};
-use tantivy::{Index, IndexWriter, Term};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 
+use tantivy::query::Query;
+use tantivy::schema::{Document, Field, Schema, SchemaBuilder, Type};
+use tantivy::{DocAddress, Index, IndexWriter, Searcher, Term};
+
+use super::super::aggregation::{Aggregation, AggregationCollector, AggregationResult};
+use super::super::collapse::{CollapseCollector, GroupValue};
+use super::super::document::DocumentEncoder;
+use super::super::explain::{explain, Explanation};
+use super::super::filter::{apply_filters, Filter};
+use super::super::parser_options::{build_query, ParserOptions};
+use super::super::search_after::{searcher_generation, Cursor, Page};
+use super::super::snapshot::Snapshot;
+use super::super::snippet::{
+    build_snippet_generator, render_snippet, FieldSnippet, SnippetOptions,
+};
+use super::super::stream::{StreamOptions, StreamRequest};
 use super::super::tantex_error::TantexError;
-use super::super::utils::{fetch_field, fetch_schema_fields, parse_query, search_with_limit};
+use super::super::utils::{
+    build_regex_query, fetch_field, fetch_projection_fields, fetch_schema_fields, find_doc_address,
+    search_with_aggregations, search_with_limit, search_with_offset,
+};
 use super::field_config::{
     build_int_options, build_string_options, build_text_options, build_trigram_options, FieldConfig,
 };
@@ -15,6 +33,9 @@ pub struct SchemaIndex {
     index: Option<Index>,
     schema: Option<Schema>,
     index_path: Option<String>,
+    multi_valued: Vec<Field>,
+    pinned_snapshots: Arc<AtomicUsize>,
+    max_snapshots: usize,
 }
 
 impl SchemaIndex {
@@ -24,15 +45,26 @@ impl SchemaIndex {
             index: None,
             schema: None,
             index_path: None,
+            multi_valued: Vec::new(),
+            pinned_snapshots: Arc::new(AtomicUsize::new(0)),
+            max_snapshots: 0,
         }
     }
 
-    pub fn add_field(&mut self, name: &str, field_config: FieldConfig) -> Result<(), TantexError> {
+    pub fn add_field(
+        &mut self,
+        name: &str,
+        field_config: FieldConfig,
+        multi: bool,
+    ) -> Result<(), TantexError> {
         if name == "" {
             return Err(TantexError::NameCannotBeBlank);
         }
         if let Some(ref mut schema_builder) = self.builder {
-            add_field_config(schema_builder, name, field_config);
+            let field = add_field_config(schema_builder, name, field_config);
+            if multi {
+                self.multi_valued.push(field);
+            }
             Ok(())
         } else {
             Err(TantexError::SchemaBuilderNotFound)
@@ -57,11 +89,24 @@ impl SchemaIndex {
         }
     }
 
-    pub fn open_index(&mut self, index_path: &str) -> Result<(), TantexError> {
+    // tantivy keeps one searcher per CPU by default; the pool is grown by
+    // `max_snapshots` so that pinned snapshots come on top of those.
+    pub fn open_index(
+        &mut self,
+        index_path: &str,
+        max_snapshots: usize,
+    ) -> Result<(), TantexError> {
         let schema = self.fetch_schema()?;
-        let index = open_or_create_index(index_path, schema.clone())?;
+        let mut index = open_or_create_index(index_path, schema.clone())?;
+        index.set_num_searchers(num_cpus::get() + max_snapshots);
+        if let Err(e1) = index.load_searchers() {
+            let message = format!("index: {:?} - reason: {:?}", index, e1);
+            let e2 = TantexError::FailedToLoadSearchers(message);
+            return Err(e2);
+        };
         self.index = Some(index);
         self.index_path = Some(index_path.to_string());
+        self.max_snapshots = max_snapshots;
         Ok(())
     }
 
@@ -69,26 +114,238 @@ impl SchemaIndex {
         &self,
         field_strings: Vec<String>,
         pattern: &str,
+        page: Page,
+        filters: Vec<Filter>,
+        parser_options: &ParserOptions,
+        aggregations: Vec<Aggregation>,
+        snapshot: Option<&Snapshot>,
+    ) -> Result<(Vec<Document>, Vec<AggregationResult>, Option<Cursor>), TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
+        let query = build_query(index, schema, fields, &pattern, parser_options)?;
+        let query = apply_filters(query, filters);
+        let leased;
+        let searcher: &Searcher = match snapshot {
+            Some(snapshot) => snapshot.fetch_searcher(&self.pinned_snapshots)?,
+            None => {
+                leased = index.searcher();
+                &*leased
+            }
+        };
+        let generation = searcher_generation(searcher);
+        if let Some(after) = page.after {
+            if after.generation != generation {
+                return Err(TantexError::StaleCursor(after.to_string()));
+            }
+        }
+        let limit = page.limit;
+        let (hits, results) = search_with_aggregations(searcher, &*query, page, aggregations)?;
+        let found: Vec<(f32, DocAddress)> =
+            hits.iter().map(|hit| (hit.score, hit.address)).collect();
+        // A short page is the last one, so it gets no cursor.
+        let last = if hits.len() < limit {
+            None
+        } else {
+            hits.last().map(|hit| Cursor {
+                generation,
+                hit: *hit,
+            })
+        };
+        Ok((retrieve_from(searcher, &found)?, results, last))
+    }
+
+    pub fn regex_search(
+        &self,
+        field_name: &str,
+        pattern: &str,
         limit: usize,
-    ) -> Result<Vec<String>, TantexError> {
+        size_limit: usize,
+    ) -> Result<Vec<Document>, TantexError> {
+        let index = self.fetch_index()?;
+        let field = self.fetch_field(&field_name)?;
+        match self.fetch_field_type(&field_name)? {
+            Type::Str => (),
+            t => return Err(TantexError::TypeCannotBeSearched(t)),
+        };
+        let query = build_regex_query(field, &pattern, size_limit)?;
+        let searcher = index.searcher();
+        let docs = search_with_limit(&searcher, &query, limit)?;
+        retrieve_from(&searcher, &docs)
+    }
+
+    pub fn snippet_search(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        limit: usize,
+        filters: Vec<Filter>,
+        parser_options: &ParserOptions,
+        options: &SnippetOptions,
+    ) -> Result<Vec<(Document, Vec<FieldSnippet>)>, TantexError> {
         let schema = self.fetch_schema()?;
         let index = self.fetch_index()?;
         let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
-        let query_parser = QueryParser::for_index(&index, fields);
-        let query = parse_query(&query_parser, &pattern)?;
-        let docs = search_with_limit(index, &query, limit)?;
-        let mut json_docs: Vec<String> = Vec::with_capacity(docs.len());
+        let query = build_query(index, schema, fields, &pattern, parser_options)?;
+        // Snippets highlight the terms of the pattern only, not filter values.
+        let filtered = apply_filters(query.box_clone(), filters);
         let searcher = index.searcher();
+        let docs = search_with_limit(&searcher, &*filtered, limit)?;
+        let mut generators = Vec::with_capacity(options.fields.len());
+        for field_name in options.fields.iter() {
+            let field = fetch_field(&schema, &field_name)?;
+            let generator = build_snippet_generator(
+                &searcher,
+                &*query,
+                &field_name,
+                field,
+                options.max_num_chars,
+            )?;
+            generators.push((field_name, generator));
+        }
+        let mut results = Vec::with_capacity(docs.len());
         for (_score, doc_address) in docs.iter() {
             match searcher.doc(*doc_address) {
-                Ok(retrieved_doc) => json_docs.push(schema.to_json(&retrieved_doc)),
-                Err(e1) => {
-                    let e2 = TantexError::DocumentRetrievalFailed(e1);
-                    return Err(e2);
+                Ok(doc) => {
+                    let snippets = generators
+                        .iter()
+                        .map(|(field_name, generator)| {
+                            let snippet = generator.snippet_from_doc(&doc);
+                            render_snippet(&field_name, &snippet, options)
+                        })
+                        .collect();
+                    results.push((doc, snippets));
                 }
+                Err(e) => return Err(TantexError::DocumentRetrievalFailed(e)),
             }
         }
-        Ok(json_docs)
+        Ok(results)
+    }
+
+    pub fn aggregate(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        filters: Vec<Filter>,
+        parser_options: &ParserOptions,
+        aggregations: Vec<Aggregation>,
+    ) -> Result<Vec<AggregationResult>, TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
+        let query = build_query(index, schema, fields, &pattern, parser_options)?;
+        let query = apply_filters(query, filters);
+        let collector = AggregationCollector::new(aggregations);
+        let searcher = index.searcher();
+        match searcher.search(&*query, &collector) {
+            Ok(results) => Ok(results),
+            Err(e) => Err(TantexError::SearchExecutionFailed(query, e)),
+        }
+    }
+
+    pub fn collapse_search(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        filters: Vec<Filter>,
+        parser_options: &ParserOptions,
+        collector: CollapseCollector,
+    ) -> Result<Vec<(Option<GroupValue>, u64, Vec<Document>)>, TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
+        let query = build_query(index, schema, fields, &pattern, parser_options)?;
+        let query = apply_filters(query, filters);
+        let searcher = index.searcher();
+        let collapsed = match searcher.search(&*query, &collector) {
+            Ok(collapsed) => collapsed,
+            Err(e) => return Err(TantexError::SearchExecutionFailed(query, e)),
+        };
+        let mut results = Vec::with_capacity(collapsed.len());
+        for (value, group) in collapsed {
+            let docs = retrieve_from(&searcher, &group.hits())?;
+            results.push((value, group.hit_count, docs));
+        }
+        Ok(results)
+    }
+
+    pub fn parse_only(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        parser_options: &ParserOptions,
+    ) -> Result<Box<Query>, TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
+        build_query(index, schema, fields, &pattern, parser_options)
+    }
+
+    pub fn explain(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        parser_options: &ParserOptions,
+        key: &Term,
+    ) -> Result<Explanation, TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        let fields: Vec<Field> = fetch_schema_fields(&schema, field_strings)?;
+        let query = build_query(index, schema, fields, &pattern, parser_options)?;
+        let searcher = index.searcher();
+        match find_doc_address(&searcher, key) {
+            Some(doc_address) => explain(&searcher, &*query, doc_address),
+            None => Err(TantexError::DocumentNotFound),
+        }
+    }
+
+    pub fn stream_request(
+        &self,
+        field_strings: Vec<String>,
+        pattern: &str,
+        filters: Vec<Filter>,
+        parser_options: ParserOptions,
+        return_fields: Vec<String>,
+        options: StreamOptions,
+    ) -> Result<StreamRequest, TantexError> {
+        let schema = self.fetch_schema()?;
+        let index = self.fetch_index()?;
+        if options.chunk_size == 0 {
+            return Err(TantexError::InvalidChunkSize(options.chunk_size));
+        }
+        let fields = fetch_schema_fields(&schema, field_strings)?;
+        // Parsed here only so that a bad pattern fails the call.
+        build_query(index, schema, fields.clone(), &pattern, &parser_options)?;
+        Ok(StreamRequest {
+            index: index.clone(),
+            schema: schema.clone(),
+            fields,
+            pattern: pattern.to_string(),
+            parser_options,
+            filters,
+            projection: fetch_projection_fields(&schema, return_fields)?,
+            multi_valued: self.multi_valued.clone(),
+            options,
+            snapshot: self.pin_snapshot()?,
+        })
+    }
+
+    pub fn pin_snapshot(&self) -> Result<Snapshot, TantexError> {
+        let index = self.fetch_index()?;
+        Snapshot::pin(index, &self.pinned_snapshots, self.max_snapshots)
+    }
+
+    pub fn document_encoder(
+        &self,
+        return_fields: Vec<String>,
+    ) -> Result<DocumentEncoder, TantexError> {
+        let schema = self.fetch_schema()?;
+        let projection = fetch_projection_fields(&schema, return_fields)?;
+        Ok(DocumentEncoder::new(schema, projection, &self.multi_valued))
+    }
+
+    pub fn multi_valued(&self) -> &[Field] {
+        &self.multi_valued
     }
 
     pub fn fetch_field(&self, field_name: &str) -> Result<Field, TantexError> {
@@ -114,15 +371,13 @@ impl SchemaIndex {
     //     }
     // }
 
-    pub fn fetch_one_by_term(&self, term: Term) -> Result<String, TantexError> {
-        let schema = self.fetch_schema()?;
+    pub fn fetch_one_by_query(&self, query: &Query) -> Result<Document, TantexError> {
         let index = self.fetch_index()?;
         let searcher = index.searcher();
-        let term_query = TermQuery::new(term, IndexRecordOption::Basic);
-        let found = search_with_limit(index, &term_query, 1)?;
+        let found = search_with_limit(&searcher, query, 1)?;
         if let Some((_score, doc_address)) = found.first() {
             match searcher.doc(*doc_address) {
-                Ok(doc) => Ok(schema.to_json(&doc)),
+                Ok(doc) => Ok(doc),
                 Err(e) => Err(TantexError::DocumentRetrievalFailed(e)),
             }
         } else {
@@ -130,19 +385,51 @@ impl SchemaIndex {
         }
     }
 
+    pub fn fetch_all_by_query(
+        &self,
+        query: &Query,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Document>, usize), TantexError> {
+        let index = self.fetch_index()?;
+        let searcher = index.searcher();
+        let (found, total) = search_with_offset(&searcher, query, limit, offset)?;
+        let docs = retrieve_from(&searcher, &found)?;
+        Ok((docs, total))
+    }
+
+    pub fn fetch_many_by_terms(
+        &self,
+        terms: &[Term],
+    ) -> Result<Vec<Option<Document>>, TantexError> {
+        let index = self.fetch_index()?;
+        let searcher = index.searcher();
+        let mut docs: Vec<Option<Document>> = Vec::with_capacity(terms.len());
+        for term in terms.iter() {
+            match find_doc_address(&searcher, term) {
+                Some(doc_address) => match searcher.doc(doc_address) {
+                    Ok(doc) => docs.push(Some(doc)),
+                    Err(e) => return Err(TantexError::DocumentRetrievalFailed(e)),
+                },
+                None => docs.push(None),
+            }
+        }
+        Ok(docs)
+    }
+
     fn fetch_index_writer(&self, heap_size: usize) -> Result<IndexWriter, TantexError> {
         let index = self.fetch_index()?;
         new_index_writer(&index, heap_size)
     }
 
-    fn fetch_index(&self) -> Result<&Index, TantexError> {
+    pub fn fetch_index(&self) -> Result<&Index, TantexError> {
         match self.index {
             Some(ref index) => Ok(index),
             None => Err(TantexError::IndexNotFound),
         }
     }
 
-    fn fetch_schema(&self) -> Result<&Schema, TantexError> {
+    pub fn fetch_schema(&self) -> Result<&Schema, TantexError> {
         match self.schema {
             Some(ref schema) => Ok(schema),
             None => Err(TantexError::SchemaNotFound),
@@ -151,23 +438,13 @@ impl SchemaIndex {
 
     pub fn write_documents(
         &self,
-        json_docs: Vec<String>,
+        docs: Vec<Document>,
         heap_size: usize,
     ) -> Result<u64, TantexError> {
         let index = self.fetch_index()?;
         let mut index_writer = self.fetch_index_writer(heap_size)?;
-        let schema = self.fetch_schema()?;
-        for json_item in json_docs {
-            match schema.parse_document(&json_item) {
-                Ok(doc) => {
-                    let _ = index_writer.add_document(doc);
-                }
-                Err(e1) => {
-                    let json_str = json_item.to_string();
-                    let e2 = TantexError::InvalidDocumentJSON(json_str, e1);
-                    return Err(e2);
-                }
-            }
+        for doc in docs {
+            let _ = index_writer.add_document(doc);
         }
         let last: u64 = match index_writer.commit() {
             Ok(last) => last,
@@ -193,6 +470,23 @@ impl SchemaIndex {
     }
 }
 
+fn retrieve_from(
+    searcher: &Searcher,
+    docs: &[(f32, DocAddress)],
+) -> Result<Vec<Document>, TantexError> {
+    let mut retrieved_docs: Vec<Document> = Vec::with_capacity(docs.len());
+    for (_score, doc_address) in docs.iter() {
+        match searcher.doc(*doc_address) {
+            Ok(retrieved_doc) => retrieved_docs.push(retrieved_doc),
+            Err(e1) => {
+                let e2 = TantexError::DocumentRetrievalFailed(e1);
+                return Err(e2);
+            }
+        }
+    }
+    Ok(retrieved_docs)
+}
+
 fn new_index_writer(index: &Index, heap_size: usize) -> Result<IndexWriter, TantexError> {
     match index.writer(heap_size) {
         Ok(writer) => Ok(writer),
@@ -203,33 +497,33 @@ fn new_index_writer(index: &Index, heap_size: usize) -> Result<IndexWriter, Tant
     }
 }
 
-fn add_field_config(schema_builder: &mut SchemaBuilder, name: &str, field_config: FieldConfig) {
+fn add_field_config(
+    schema_builder: &mut SchemaBuilder,
+    name: &str,
+    field_config: FieldConfig,
+) -> Field {
     match field_config {
         U64 { stored, fast } => {
             let int_options = build_int_options(stored, fast);
-            schema_builder.add_u64_field(name, int_options);
+            schema_builder.add_u64_field(name, int_options)
         }
         I64 { stored, fast } => {
             let int_options = build_int_options(stored, fast);
-            schema_builder.add_i64_field(name, int_options);
+            schema_builder.add_i64_field(name, int_options)
         }
         Str { stored } => {
             let string_options = build_string_options(stored);
-            schema_builder.add_text_field(name, string_options);
+            schema_builder.add_text_field(name, string_options)
         }
         Text { stored } => {
             let text_options = build_text_options(stored);
-            schema_builder.add_text_field(name, text_options);
+            schema_builder.add_text_field(name, text_options)
         }
         Trigram { stored } => {
             let text_options = build_trigram_options(stored);
-            schema_builder.add_text_field(name, text_options);
+            schema_builder.add_text_field(name, text_options)
         }
-        Facet => {
-            schema_builder.add_facet_field(name);
-        }
-        Bytes => {
-            schema_builder.add_bytes_field(name);
-        }
-    };
+        Facet => schema_builder.add_facet_field(name),
+        Bytes => schema_builder.add_bytes_field(name),
+    }
 }
@@ -0,0 +1,293 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::{DocAddress, DocId, Score, Searcher, SegmentLocalId, SegmentReader};
+
+use super::tantex_error::TantexError;
+
+// Hits are ranked by descending score, ties broken by ascending doc address,
+// so every hit has a unique position and a page can resume right after the
+// last hit of the previous one.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub score: Score,
+    pub address: DocAddress,
+}
+
+impl Hit {
+    fn rank(&self, other: &Hit) -> Ordering {
+        let DocAddress(segment_ord, doc) = self.address;
+        let DocAddress(other_segment_ord, other_doc) = other.address;
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| (segment_ord, doc).cmp(&(other_segment_ord, other_doc)))
+    }
+}
+
+// Ordered so the best hit sorts first and a max-heap keeps the worst on top.
+impl Ord for Hit {
+    fn cmp(&self, other: &Hit) -> Ordering {
+        self.rank(other)
+    }
+}
+
+impl PartialOrd for Hit {
+    fn partial_cmp(&self, other: &Hit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Hit {
+    fn eq(&self, other: &Hit) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Hit {}
+
+// Scores and doc addresses are only stable for one searcher generation: a
+// commit or merge in between changes the idf behind every score and may
+// renumber segments. A cursor therefore carries the generation it was cut
+// from and is rejected by searchers of any other generation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    pub generation: u64,
+    pub hit: Hit,
+}
+
+// The cursor is `generation.score_bits.segment_ord.doc` in hex; callers
+// should treat it as opaque.
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let DocAddress(segment_ord, doc) = self.hit.address;
+        write!(
+            f,
+            "{:x}.{:x}.{:x}.{:x}",
+            self.generation,
+            self.hit.score.to_bits(),
+            segment_ord,
+            doc
+        )
+    }
+}
+
+impl Cursor {
+    pub fn parse(cursor: &str) -> Result<Cursor, TantexError> {
+        let invalid = || TantexError::InvalidCursor(cursor.to_string());
+        let parts: Vec<&str> = cursor.split('.').collect();
+        match parts.as_slice() {
+            [generation, score_bits, segment_ord, doc] => {
+                let generation = u64::from_str_radix(generation, 16).map_err(|_| invalid())?;
+                let score_bits = u32::from_str_radix(score_bits, 16).map_err(|_| invalid())?;
+                let segment_ord = u32::from_str_radix(segment_ord, 16).map_err(|_| invalid())?;
+                let doc = u32::from_str_radix(doc, 16).map_err(|_| invalid())?;
+                Ok(Cursor {
+                    generation,
+                    hit: Hit {
+                        score: f32::from_bits(score_bits),
+                        address: DocAddress(segment_ord, doc),
+                    },
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Fingerprints the segments a searcher reads along with their deletes. It is
+// only compared for equality, never decoded.
+pub fn searcher_generation(searcher: &Searcher) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for segment_reader in searcher.segment_readers() {
+        segment_reader.segment_id().uuid_string().hash(&mut hasher);
+        segment_reader.num_deleted_docs().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+pub struct Page {
+    pub limit: usize,
+    pub after: Option<Cursor>,
+}
+
+// Collects the best `limit` hits ranked after `after`. Unlike TopDocs it
+// accepts a limit of 0.
+pub struct SearchAfterCollector {
+    page: Page,
+}
+
+impl SearchAfterCollector {
+    pub fn new(page: Page) -> SearchAfterCollector {
+        SearchAfterCollector { page }
+    }
+}
+
+pub struct SearchAfterSegmentCollector {
+    segment_local_id: SegmentLocalId,
+    limit: usize,
+    after: Option<Hit>,
+    hits: BinaryHeap<Hit>,
+}
+
+impl Collector for SearchAfterCollector {
+    type Fruit = Vec<Hit>;
+    type Child = SearchAfterSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_local_id: SegmentLocalId,
+        _reader: &SegmentReader,
+    ) -> tantivy::Result<SearchAfterSegmentCollector> {
+        Ok(SearchAfterSegmentCollector {
+            segment_local_id,
+            limit: self.page.limit,
+            after: self.page.after.map(|cursor| cursor.hit),
+            hits: BinaryHeap::with_capacity(self.page.limit + 1),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<Vec<Hit>>) -> tantivy::Result<Vec<Hit>> {
+        let mut hits: Vec<Hit> = segment_fruits.into_iter().flatten().collect();
+        hits.sort();
+        hits.truncate(self.page.limit);
+        Ok(hits)
+    }
+}
+
+impl SegmentCollector for SearchAfterSegmentCollector {
+    type Fruit = Vec<Hit>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let hit = Hit {
+            score,
+            address: DocAddress(self.segment_local_id, doc),
+        };
+        if let Some(after) = self.after {
+            if hit <= after {
+                return;
+            }
+        }
+        if self.hits.len() >= self.limit {
+            match self.hits.peek() {
+                Some(worst) if hit < *worst => {
+                    self.hits.pop();
+                }
+                _ => return,
+            }
+        }
+        self.hits.push(hit);
+    }
+
+    fn harvest(self) -> Vec<Hit> {
+        self.hits.into_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(score: Score, segment_ord: u32, doc: DocId) -> Hit {
+        Hit {
+            score,
+            address: DocAddress(segment_ord, doc),
+        }
+    }
+
+    fn collector(limit: usize, after: Option<Hit>) -> SearchAfterSegmentCollector {
+        SearchAfterSegmentCollector {
+            segment_local_id: 1,
+            limit,
+            after,
+            hits: BinaryHeap::new(),
+        }
+    }
+
+    #[test]
+    fn ranks_by_descending_score_then_ascending_address() {
+        let mut hits = vec![
+            hit(1.0, 0, 0),
+            hit(2.0, 1, 5),
+            hit(2.0, 0, 9),
+            hit(2.0, 1, 3),
+            hit(0.5, 0, 1),
+        ];
+        hits.sort();
+        assert_eq!(
+            hits.iter()
+                .map(|hit| hit.address)
+                .collect::<Vec<DocAddress>>(),
+            vec![
+                DocAddress(0, 9),
+                DocAddress(1, 3),
+                DocAddress(1, 5),
+                DocAddress(0, 0),
+                DocAddress(0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_cursors() {
+        let cursor = Cursor {
+            generation: u64::max_value(),
+            hit: hit(3.25, 2, 4_000_000),
+        };
+        let parsed = Cursor::parse(&cursor.to_string()).ok();
+        assert_eq!(parsed, Some(cursor));
+        assert_eq!(parsed.map(|parsed| parsed.hit.score), Some(3.25));
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        for cursor in &[
+            "",
+            "1.2.3",
+            "1.2.3.4.5",
+            "1.2.x.4",
+            "1.100000000.3.4",
+            "1.2.3.-4",
+        ] {
+            assert!(Cursor::parse(cursor).is_err(), "{:?} was accepted", cursor);
+        }
+    }
+
+    #[test]
+    fn keeps_the_best_hits_up_to_the_limit() {
+        let mut collector = collector(2, None);
+        for (doc, score) in [1.0, 4.0, 2.0, 3.0].iter().enumerate() {
+            collector.collect(doc as DocId, *score);
+        }
+        let mut hits = collector.harvest();
+        hits.sort();
+        assert_eq!(hits, vec![hit(4.0, 1, 1), hit(3.0, 1, 3)]);
+    }
+
+    #[test]
+    fn skips_hits_ranked_up_to_the_cursor() {
+        let mut collector = collector(10, Some(hit(2.0, 1, 2)));
+        for (doc, score) in [2.0, 4.0, 2.0, 2.0, 1.0].iter().enumerate() {
+            collector.collect(doc as DocId, *score);
+        }
+        let mut hits = collector.harvest();
+        hits.sort();
+        assert_eq!(hits, vec![hit(2.0, 1, 3), hit(1.0, 1, 4)]);
+    }
+
+    #[test]
+    fn collects_nothing_with_a_limit_of_zero() {
+        let mut collector = collector(0, None);
+        collector.collect(0, 1.0);
+        assert!(collector.harvest().is_empty());
+    }
+}
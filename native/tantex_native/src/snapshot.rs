@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tantivy::{Index, LeasedItem, Searcher};
+
+use super::tantex_error::TantexError;
+
+// A searcher pinned to the segments that were live when it was taken, so
+// every page of a session sees the same documents, scores and doc addresses.
+//
+// The searcher is leased from the index's pool, and `index.searcher()` blocks
+// once every searcher of the pool is leased. `open_index` sizes the pool at
+// one searcher per CPU plus `max_pinned`, and at most `max_pinned` snapshots
+// may be pinned at a time, so pinned snapshots never take the searchers that
+// regular searches run on.
+pub struct Snapshot {
+    searcher: Option<LeasedItem<Searcher>>,
+    pinned: Arc<AtomicUsize>,
+}
+
+impl Snapshot {
+    pub fn pin(
+        index: &Index,
+        pinned: &Arc<AtomicUsize>,
+        max_pinned: usize,
+    ) -> Result<Snapshot, TantexError> {
+        let previous = pinned.fetch_add(1, Ordering::SeqCst);
+        if previous >= max_pinned {
+            pinned.fetch_sub(1, Ordering::SeqCst);
+            return Err(TantexError::TooManySnapshots(max_pinned));
+        }
+        Ok(Snapshot {
+            searcher: Some(index.searcher()),
+            pinned: pinned.clone(),
+        })
+    }
+
+    // Fails for a released snapshot or one pinned on another index.
+    pub fn fetch_searcher(&self, pinned: &Arc<AtomicUsize>) -> Result<&Searcher, TantexError> {
+        if !Arc::ptr_eq(&self.pinned, pinned) {
+            return Err(TantexError::SnapshotIndexMismatch);
+        }
+        self.searcher()
+    }
+
+    pub fn searcher(&self) -> Result<&Searcher, TantexError> {
+        match self.searcher {
+            Some(ref searcher) => Ok(&**searcher),
+            None => Err(TantexError::SnapshotReleased),
+        }
+    }
+
+    // Hands the searcher back to the pool right away instead of waiting for
+    // the resource to be garbage collected.
+    pub fn release(&mut self) {
+        if self.searcher.take().is_some() {
+            self.pinned.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
@@ -0,0 +1,127 @@
+use tantivy::query::Query;
+use tantivy::schema::Field;
+use tantivy::{Searcher, Snippet, SnippetGenerator};
+
+use super::tantex_error::TantexError;
+
+pub struct SnippetOptions {
+    pub fields: Vec<String>,
+    pub pre_tag: String,
+    pub post_tag: String,
+    pub max_num_chars: usize,
+}
+
+// (field_name, html, fragment, highlighted byte ranges within fragment)
+pub type FieldSnippet = (String, String, String, Vec<(usize, usize)>);
+
+pub fn build_snippet_generator(
+    searcher: &Searcher,
+    query: &Query,
+    field_name: &str,
+    field: Field,
+    max_num_chars: usize,
+) -> Result<SnippetGenerator, TantexError> {
+    match SnippetGenerator::new(searcher, query, field) {
+        Ok(mut generator) => {
+            generator.set_max_num_chars(max_num_chars);
+            Ok(generator)
+        }
+        Err(e) => Err(TantexError::SnippetGenerationFailed(
+            field_name.to_string(),
+            e,
+        )),
+    }
+}
+
+pub fn render_snippet(
+    field_name: &str,
+    snippet: &Snippet,
+    options: &SnippetOptions,
+) -> FieldSnippet {
+    let fragment = snippet.fragments();
+    let highlighted: Vec<(usize, usize)> = snippet
+        .highlighted()
+        .iter()
+        .map(|section| section.bounds())
+        .collect();
+    let html = render_html(fragment, &highlighted, &options.pre_tag, &options.post_tag);
+    (
+        field_name.to_string(),
+        html,
+        fragment.to_string(),
+        highlighted,
+    )
+}
+
+// Snippet::to_html hardcodes <b></b>, so the fragment is rebuilt here with
+// the caller's tags. The tags are emitted as-is; only the text is escaped.
+fn render_html(
+    fragment: &str,
+    highlighted: &[(usize, usize)],
+    pre_tag: &str,
+    post_tag: &str,
+) -> String {
+    let mut html = String::with_capacity(fragment.len());
+    let mut cursor = 0;
+    for &(start, end) in highlighted.iter() {
+        html.push_str(&escape_html(&fragment[cursor..start]));
+        html.push_str(pre_tag);
+        html.push_str(&escape_html(&fragment[start..end]));
+        html.push_str(post_tag);
+        cursor = end;
+    }
+    html.push_str(&escape_html(&fragment[cursor..]));
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text_without_highlights() {
+        assert_eq!(
+            render_html("<a href=\"x\">Tom & Jerry's</a>", &[], "<b>", "</b>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#x27;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn wraps_highlights_in_the_given_tags() {
+        assert_eq!(
+            render_html("the quick fox", &[(4, 9), (10, 13)], "<em>", "</em>"),
+            "the <em>quick</em> <em>fox</em>"
+        );
+    }
+
+    #[test]
+    fn escapes_highlighted_text_but_not_the_tags() {
+        assert_eq!(
+            render_html("a <b> c", &[(2, 5)], "<mark class=\"hit\">", "</mark>"),
+            "a <mark class=\"hit\">&lt;b&gt;</mark> c"
+        );
+    }
+
+    #[test]
+    fn keeps_multibyte_text_around_highlights() {
+        assert_eq!(
+            render_html("café <crème>", &[(0, 5)], "[", "]"),
+            "[café] &lt;crème&gt;"
+        );
+    }
+}
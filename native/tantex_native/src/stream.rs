@@ -0,0 +1,237 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustler::env::OwnedEnv;
+use rustler::types::pid::Pid;
+use rustler::Encoder;
+use tantivy::schema::{Document, Field, Schema};
+use tantivy::{DocAddress, DocSet, Index};
+
+use super::atoms;
+use super::document::DocumentEncoder;
+use super::filter::{apply_filters, Filter};
+use super::parser_options::{build_query, ParserOptions};
+use super::snapshot::Snapshot;
+use super::tantex_error::TantexError;
+
+static NEXT_STREAM_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub struct StreamOptions {
+    pub chunk_size: usize,
+    pub timeout: Duration,
+}
+
+// Everything a stream needs, owned, so it can move to the stream's thread.
+// The pattern has already been parsed once by the caller to report errors,
+// but is parsed again on that thread since tantivy queries are not Send.
+pub struct StreamRequest {
+    pub index: Index,
+    pub schema: Schema,
+    pub fields: Vec<Field>,
+    pub pattern: String,
+    pub parser_options: ParserOptions,
+    pub filters: Vec<Filter>,
+    pub projection: Option<Vec<Field>>,
+    pub multi_valued: Vec<Field>,
+    pub options: StreamOptions,
+    pub snapshot: Snapshot,
+}
+
+struct Demand {
+    chunks: usize,
+    cancelled: bool,
+}
+
+// Shared between a stream's thread and its handle.
+struct StreamControl {
+    demand: Mutex<Demand>,
+    changed: Condvar,
+}
+
+impl StreamControl {
+    fn new() -> StreamControl {
+        StreamControl {
+            demand: Mutex::new(Demand {
+                chunks: 0,
+                cancelled: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn update<F: FnOnce(&mut Demand)>(&self, f: F) {
+        let mut demand = self.demand.lock().unwrap();
+        f(&mut demand);
+        self.changed.notify_all();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.demand.lock().unwrap().cancelled
+    }
+
+    // Blocks until the receiver asks for one more chunk. Returns false once
+    // the stream is cancelled.
+    fn wait_for_demand(&self, timeout: Duration) -> Result<bool, TantexError> {
+        let deadline = Instant::now() + timeout;
+        let mut demand = self.demand.lock().unwrap();
+        loop {
+            if demand.cancelled {
+                return Ok(false);
+            }
+            if demand.chunks > 0 {
+                demand.chunks -= 1;
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TantexError::StreamTimedOut(timeout.as_millis() as u64));
+            }
+            demand = self.changed.wait_timeout(demand, deadline - now).unwrap().0;
+        }
+    }
+}
+
+// The receiver's end of a stream. Dropping it, which happens once the
+// resource is garbage collected, cancels the stream.
+pub struct StreamHandle {
+    control: Arc<StreamControl>,
+}
+
+impl StreamHandle {
+    pub fn ack(&self) {
+        self.control.update(|demand| demand.chunks += 1);
+    }
+
+    pub fn cancel(&self) {
+        self.control.update(|demand| demand.cancelled = true);
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Sends every document matching the request to `pid` from a thread of its
+// own, as
+//
+//   {:tantex_stream, stream_id, {:documents, [doc]}}  (chunk_size docs at most)
+//   {:tantex_stream, stream_id, :done}
+//   {:tantex_stream, stream_id, {:error, reason}}
+//
+// Matches are walked segment by segment with the query's scorer, unscored
+// and unsorted. After each chunk the thread waits for the receiver to ack it
+// before reading the next one, so at most one chunk is in flight. A cancelled
+// stream stops without sending anything more, and a stream left without an
+// ack for `timeout` stops with an error. Either way the walk ends and the
+// stream's snapshot is released.
+pub fn spawn_stream(pid: Pid, request: StreamRequest) -> (usize, StreamHandle) {
+    let stream_id = NEXT_STREAM_ID.fetch_add(1, Ordering::SeqCst);
+    let control = Arc::new(StreamControl::new());
+    let handle = StreamHandle {
+        control: control.clone(),
+    };
+    thread::spawn(move || {
+        let mut env = OwnedEnv::new();
+        let result = stream_documents(&mut env, &pid, stream_id, &control, request);
+        if let Ok(false) = result {
+            return;
+        }
+        env.send_and_clear(&pid, |env| {
+            let status = match result {
+                Ok(_) => atoms::done().encode(env),
+                Err(e) => (atoms::error(), e.to_reason(env)).encode(env),
+            };
+            (atoms::tantex_stream(), stream_id, status).encode(env)
+        });
+    });
+    (stream_id, handle)
+}
+
+// Returns false when the stream was cancelled before its last chunk, or
+// already ended on a chunk that could not be encoded.
+fn stream_documents(
+    env: &mut OwnedEnv,
+    pid: &Pid,
+    stream_id: usize,
+    control: &StreamControl,
+    request: StreamRequest,
+) -> Result<bool, TantexError> {
+    let searcher = request.snapshot.searcher()?;
+    let query = build_query(
+        &request.index,
+        &request.schema,
+        request.fields,
+        &request.pattern,
+        &request.parser_options,
+    )?;
+    let query = apply_filters(query, request.filters);
+    let weight = match query.weight(searcher, false) {
+        Ok(weight) => weight,
+        Err(e) => return Err(TantexError::SearchExecutionFailed(query, e)),
+    };
+    let encoder = DocumentEncoder::new(&request.schema, request.projection, &request.multi_valued);
+    let chunk_size = request.options.chunk_size;
+    let mut chunk: Vec<Document> = Vec::with_capacity(chunk_size);
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let mut scorer = match weight.scorer(segment_reader) {
+            Ok(scorer) => scorer,
+            Err(e) => return Err(TantexError::SearchExecutionFailed(query, e)),
+        };
+        while scorer.advance() {
+            let doc = scorer.doc();
+            if segment_reader.is_deleted(doc) {
+                continue;
+            }
+            match searcher.doc(DocAddress(segment_ord as u32, doc)) {
+                Ok(retrieved_doc) => chunk.push(retrieved_doc),
+                Err(e) => return Err(TantexError::DocumentRetrievalFailed(e)),
+            }
+            if chunk.len() >= chunk_size {
+                if control.is_cancelled() {
+                    return Ok(false);
+                }
+                if !send_chunk(env, pid, stream_id, &encoder, &chunk) {
+                    return Ok(false);
+                }
+                chunk.clear();
+                if !control.wait_for_demand(request.options.timeout)? {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    if control.is_cancelled() {
+        return Ok(false);
+    }
+    if !chunk.is_empty() && !send_chunk(env, pid, stream_id, &encoder, &chunk) {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Sends the receiver an error instead when the chunk cannot be encoded, and
+// returns false then.
+fn send_chunk(
+    env: &mut OwnedEnv,
+    pid: &Pid,
+    stream_id: usize,
+    encoder: &DocumentEncoder,
+    docs: &[Document],
+) -> bool {
+    let mut encoded = true;
+    env.send_and_clear(pid, |env| {
+        let message = match encoder.encode_all(env, docs) {
+            Ok(docs) => (atoms::documents(), docs).encode(env),
+            Err(e) => {
+                encoded = false;
+                (atoms::error(), e.to_reason(env)).encode(env)
+            }
+        };
+        (atoms::tantex_stream(), stream_id, message).encode(env)
+    });
+    encoded
+}
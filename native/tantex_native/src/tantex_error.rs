@@ -1,7 +1,7 @@
 use super::atoms;
 use rustler::types::atom::Atom;
-use tantivy::query::Query;
-use tantivy::schema::DocParsingError;
+use rustler::{Encoder, Env, Term};
+use tantivy::query::{Query, QueryParserError};
 use tantivy::schema::Type;
 use tantivy::TantivyError;
 
@@ -17,22 +17,44 @@ pub enum TantexError {
     FailedToLoadSearchers(String),
     FieldNotFound(String),
     DocumentNotFound,
-    InvalidQuery(String),
+    InvalidQuery(String, QueryParserError),
     SearchExecutionFailed(Box<Query>, TantivyError),
     DocumentRetrievalFailed(TantivyError),
-    InvalidDocumentJSON(String, DocParsingError),
+    DocumentEncodingFailed(String),
+    DocumentMustBeMap(String),
+    InvalidDocumentValue(String, String),
     SchemaBuilderNotFound,
     SchemaNotFound,
     IndexNotFound,
     TypeCannotBeSearched(Type),
     InvalidFieldData(Type, String),
+    InvalidRegex(String, fst_regex::Error),
+    SnippetGenerationFailed(String, TantivyError),
+    ResultWindowTooLarge(usize),
+    FieldNotIndexed(String),
+    UnknownTokenizer(String),
+    TermHasNoTokens(String),
+    TermHasMultipleTokens(String, usize),
+    InvalidFilter(String),
+    InvalidAggregation(String),
+    FieldNotFast(String),
+    InvalidCursor(String),
+    StaleCursor(String),
+    InvalidCollapseField(String),
+    TooManySnapshots(usize),
+    SnapshotReleased,
+    SnapshotIndexMismatch,
+    InvalidChunkSize(usize),
+    StreamTimedOut(u64),
 }
 
 use TantexError::*;
 
 impl TantexError {
-    pub fn to_reason(&self) -> (Atom, String) {
-        match self {
+    // The `reason` of `{:error, reason}`: `{atom, message}` for most errors
+    // and `{atom, detail}` where the detail is structured.
+    pub fn to_reason<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let reason: (Atom, String) = match self {
             TypeCannotBeFast(t) => (atoms::cannot_be_fast(), t.to_string()),
             InvalidType(t) => (atoms::invalid_type(), t.to_string()),
             TypeIsNotStored(t) => (atoms::cannot_be_stored(), t.to_string()),
@@ -54,7 +76,10 @@ impl TantexError {
             FailedToLoadSearchers(message) => {
                 (atoms::failed_to_load_searchers(), message.to_string())
             }
-            InvalidQuery(query) => (atoms::invalid_query_format(), query.to_string()),
+            InvalidQuery(pattern, parser_error) => {
+                let detail = encode_query_parser_error(env, pattern, parser_error);
+                return (atoms::invalid_query_format(), detail).encode(env);
+            }
             SearchExecutionFailed(query, tantivy_error) => {
                 let message = format!("query: {:?} - error: {:?}", query, tantivy_error);
                 (atoms::search_execution_failed(), message)
@@ -63,9 +88,13 @@ impl TantexError {
                 let message = format!("error: {:?}", tantivy_error);
                 (atoms::document_retrieval_failed(), message)
             }
-            InvalidDocumentJSON(json, tantivy_error) => {
-                let message = format!("json: {:?} - error: {:?}", json, tantivy_error);
-                (atoms::invalid_document_json(), message)
+            DocumentEncodingFailed(field_name) => {
+                (atoms::document_encoding_failed(), field_name.to_string())
+            }
+            DocumentMustBeMap(document) => (atoms::document_must_be_map(), document.to_string()),
+            InvalidDocumentValue(field_name, value) => {
+                let message = format!("field_name: {:?} - value: {}", field_name, value);
+                (atoms::invalid_document_value(), message)
             }
             DocumentNotFound => (atoms::document_not_found(), "".to_string()),
             SchemaBuilderNotFound => (atoms::schema_builder_not_found(), "".to_string()),
@@ -79,6 +108,92 @@ impl TantexError {
                 let message = format!("type: {:?} - field_name: {:?}", tantivy_type, field_name);
                 (atoms::invalid_field_data(), message)
             }
+            InvalidRegex(pattern, reason) => {
+                let message = format!("pattern: {:?} - reason: {}", pattern, reason);
+                (atoms::invalid_regex(), message)
+            }
+            SnippetGenerationFailed(field_name, tantivy_error) => {
+                let message = format!("field_name: {:?} - error: {:?}", field_name, tantivy_error);
+                (atoms::snippet_generation_failed(), message)
+            }
+            ResultWindowTooLarge(max_window) => {
+                let message = format!("offset + limit must be at most {}", max_window);
+                (atoms::result_window_too_large(), message)
+            }
+            FieldNotIndexed(field_name) => (atoms::field_not_indexed(), field_name.to_string()),
+            UnknownTokenizer(tokenizer) => (atoms::unknown_tokenizer(), tokenizer.to_string()),
+            TermHasNoTokens(field_name) => {
+                let message = format!("field_name: {:?}", field_name);
+                (atoms::term_has_no_tokens(), message)
+            }
+            TermHasMultipleTokens(field_name, count) => {
+                let message = format!("field_name: {:?} - tokens: {}", field_name, count);
+                (atoms::term_has_multiple_tokens(), message)
+            }
+            InvalidFilter(filter) => (atoms::invalid_filter(), filter.to_string()),
+            InvalidAggregation(aggregation) => {
+                (atoms::invalid_aggregation(), aggregation.to_string())
+            }
+            FieldNotFast(field_name) => (atoms::field_not_fast(), field_name.to_string()),
+            InvalidCursor(cursor) => (atoms::invalid_cursor(), cursor.to_string()),
+            StaleCursor(cursor) => {
+                let message = format!("cursor: {:?} - reason: index_changed", cursor);
+                (atoms::invalid_cursor(), message)
+            }
+            InvalidCollapseField(field_name) => {
+                (atoms::invalid_collapse_field(), field_name.to_string())
+            }
+            TooManySnapshots(max_pinned) => {
+                let message = format!("max_pinned: {:?}", max_pinned);
+                (atoms::too_many_snapshots(), message)
+            }
+            SnapshotReleased => (atoms::snapshot_released(), "".to_string()),
+            SnapshotIndexMismatch => (atoms::snapshot_index_mismatch(), "".to_string()),
+            InvalidChunkSize(chunk_size) => (atoms::invalid_chunk_size(), chunk_size.to_string()),
+            StreamTimedOut(timeout) => {
+                let message = format!("timeout: {:?}", timeout);
+                (atoms::stream_timed_out(), message)
+            }
+        };
+        reason.encode(env)
+    }
+}
+
+// Query parser failures reach Elixir as `{reason, detail}` tuples, e.g.
+// `{:field_does_not_exist, "nope"}`, so that callers can match on them.
+// tantivy 0.8 drops the position of a syntax error, so `:syntax_error` only
+// carries the pattern.
+fn encode_query_parser_error<'a>(
+    env: Env<'a>,
+    pattern: &str,
+    parser_error: &QueryParserError,
+) -> Term<'a> {
+    match parser_error {
+        QueryParserError::SyntaxError => (atoms::syntax_error(), pattern).encode(env),
+        QueryParserError::FieldDoesNotExist(field_name) => {
+            (atoms::field_does_not_exist(), field_name.as_str()).encode(env)
+        }
+        QueryParserError::ExpectedInt(e) => (atoms::expected_integer(), e.to_string()).encode(env),
+        QueryParserError::AllButQueryForbidden => {
+            (atoms::only_excluding_clauses(), pattern).encode(env)
+        }
+        QueryParserError::NoDefaultFieldDeclared => {
+            (atoms::no_default_field(), pattern).encode(env)
+        }
+        QueryParserError::FieldNotIndexed(field_name) => {
+            (atoms::field_not_indexed(), field_name.as_str()).encode(env)
+        }
+        QueryParserError::FieldDoesNotHavePositionsIndexed(field_name) => {
+            (atoms::field_has_no_positions(), field_name.as_str()).encode(env)
+        }
+        QueryParserError::UnknownTokenizer(field_name, tokenizer) => (
+            atoms::unknown_tokenizer(),
+            field_name.as_str(),
+            tokenizer.as_str(),
+        )
+            .encode(env),
+        QueryParserError::RangeMustNotHavePhrase => {
+            (atoms::range_with_phrase(), pattern).encode(env)
         }
     }
 }
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema};
+use tantivy::{DocId, DocSet, SegmentId, SegmentReader};
+
+// Segments are immutable, so a table built once serves every later search
+// until the segment is merged away; the oldest tables are dropped past this.
+const MAX_CACHED_TABLES: usize = 64;
+
+lazy_static! {
+    static ref CACHE: Mutex<TermOrdsCache> = Mutex::new(TermOrdsCache::new());
+}
+
+// Only untokenized `string` fields hold one term per value; a tokenized
+// `text` field holds one term per token.
+pub fn is_raw_string_field(schema: &Schema, field: Field) -> bool {
+    match schema.get_field_entry(field).field_type() {
+        FieldType::Str(text_options) => match text_options.get_indexing_options() {
+            Some(indexing) => indexing.tokenizer() == "raw",
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+// The terms each document of a segment holds for a field, as ordinals into
+// the segment's term dictionary, in term order.
+pub struct TermOrds {
+    offsets: Vec<u32>,
+    ords: Vec<u32>,
+    terms: Vec<String>,
+}
+
+impl TermOrds {
+    // tantivy 0.8 has no doc-to-term lookup for string fields, so the term
+    // dictionary is walked and every posting of the field is read once.
+    fn build(reader: &SegmentReader, field: Field) -> TermOrds {
+        let inverted_index = reader.inverted_index(field);
+        let mut terms: Vec<String> = Vec::new();
+        let mut pairs: Vec<(DocId, u32)> = Vec::new();
+        let mut term_stream = inverted_index.terms().stream();
+        while term_stream.advance() {
+            let ord = terms.len() as u32;
+            terms.push(String::from_utf8_lossy(term_stream.key()).into_owned());
+            let mut postings = inverted_index
+                .read_postings_from_terminfo(term_stream.value(), IndexRecordOption::Basic);
+            while postings.advance() {
+                pairs.push((postings.doc(), ord));
+            }
+        }
+        pairs.sort();
+        let mut offsets = vec![0u32; reader.max_doc() as usize + 1];
+        for &(doc, _) in pairs.iter() {
+            offsets[doc as usize + 1] += 1;
+        }
+        for doc in 1..offsets.len() {
+            offsets[doc] += offsets[doc - 1];
+        }
+        let ords = pairs.into_iter().map(|(_, ord)| ord).collect();
+        TermOrds {
+            offsets,
+            ords,
+            terms,
+        }
+    }
+
+    pub fn ords(&self, doc: DocId) -> &[u32] {
+        let doc = doc as usize;
+        &self.ords[self.offsets[doc] as usize..self.offsets[doc + 1] as usize]
+    }
+
+    pub fn term(&self, ord: u32) -> &str {
+        &self.terms[ord as usize]
+    }
+
+    pub fn num_terms(&self) -> usize {
+        self.terms.len()
+    }
+}
+
+struct TermOrdsCache {
+    tables: HashMap<(SegmentId, Field), Arc<TermOrds>>,
+    order: VecDeque<(SegmentId, Field)>,
+}
+
+impl TermOrdsCache {
+    fn new() -> TermOrdsCache {
+        TermOrdsCache {
+            tables: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, key: (SegmentId, Field), table: Arc<TermOrds>) {
+        if self.tables.insert(key, table).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > MAX_CACHED_TABLES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.tables.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Returns the segment's table for `field`, building it on first use. The
+// table is built outside the lock, so concurrent searches never wait on one
+// another; at worst two of them build the same table.
+pub fn segment_term_ords(reader: &SegmentReader, field: Field) -> Arc<TermOrds> {
+    let key = (reader.segment_id(), field);
+    if let Some(table) = CACHE.lock().unwrap().tables.get(&key) {
+        return table.clone();
+    }
+    let table = Arc::new(TermOrds::build(reader, field));
+    CACHE.lock().unwrap().insert(key, table.clone());
+    table
+}
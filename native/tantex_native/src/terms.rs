@@ -0,0 +1,107 @@
+use rustler::Term;
+use tantivy::query::{PhraseQuery, Query, TermQuery};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema, TextOptions, Type};
+use tantivy::tokenizer::Token;
+use tantivy::Index;
+
+use super::tantex_error::TantexError;
+use super::utils::fetch_field;
+use TantexError::{
+    FieldNotIndexed, InvalidFieldData, TermHasMultipleTokens, TermHasNoTokens,
+    TypeCannotBeSearched, UnknownTokenizer,
+};
+
+// Text values are run through the field's analyzer, exactly as they were at
+// index time, so a single input may produce zero, one or several terms.
+pub fn decode_terms(
+    index: &Index,
+    schema: &Schema,
+    field_name: &str,
+    value: Term,
+) -> Result<Vec<tantivy::Term>, TantexError> {
+    let field = fetch_field(schema, field_name)?;
+    let bad_data = |t: Type| InvalidFieldData(t, field_name.to_string());
+    match schema.get_field_entry(field).field_type() {
+        FieldType::I64(_) => {
+            let val: i64 = value.decode().map_err(|_| bad_data(Type::I64))?;
+            Ok(vec![tantivy::Term::from_field_i64(field, val)])
+        }
+        FieldType::U64(_) => {
+            let val: u64 = value.decode().map_err(|_| bad_data(Type::U64))?;
+            Ok(vec![tantivy::Term::from_field_u64(field, val)])
+        }
+        FieldType::Str(text_options) => {
+            let val: String = value.decode().map_err(|_| bad_data(Type::Str))?;
+            analyze_text(index, field_name, field, text_options, &val)
+        }
+        field_type => Err(TypeCannotBeSearched(field_type.value_type())),
+    }
+}
+
+pub fn decode_single_term(
+    index: &Index,
+    schema: &Schema,
+    field_name: &str,
+    value: Term,
+) -> Result<tantivy::Term, TantexError> {
+    let mut terms = decode_terms(index, schema, field_name, value)?;
+    match terms.len() {
+        0 => Err(TermHasNoTokens(field_name.to_string())),
+        1 => Ok(terms.remove(0)),
+        n => Err(TermHasMultipleTokens(field_name.to_string(), n)),
+    }
+}
+
+// Several tokens become a phrase match, which needs positions in the index.
+pub fn build_term_query(
+    schema: &Schema,
+    field_name: &str,
+    mut terms: Vec<tantivy::Term>,
+) -> Result<Box<Query>, TantexError> {
+    match terms.len() {
+        0 => Err(TermHasNoTokens(field_name.to_string())),
+        1 => {
+            let term_query = TermQuery::new(terms.remove(0), IndexRecordOption::Basic);
+            Ok(Box::new(term_query))
+        }
+        n => {
+            if has_positions(schema, terms[0].field()) {
+                Ok(Box::new(PhraseQuery::new(terms)))
+            } else {
+                Err(TermHasMultipleTokens(field_name.to_string(), n))
+            }
+        }
+    }
+}
+
+fn analyze_text(
+    index: &Index,
+    field_name: &str,
+    field: Field,
+    text_options: &TextOptions,
+    text: &str,
+) -> Result<Vec<tantivy::Term>, TantexError> {
+    let indexing = match text_options.get_indexing_options() {
+        Some(indexing) => indexing,
+        None => return Err(FieldNotIndexed(field_name.to_string())),
+    };
+    let tokenizer = match index.tokenizers().get(indexing.tokenizer()) {
+        Some(tokenizer) => tokenizer,
+        None => return Err(UnknownTokenizer(indexing.tokenizer().to_string())),
+    };
+    let mut terms: Vec<tantivy::Term> = Vec::new();
+    tokenizer.token_stream(text).process(&mut |token: &Token| {
+        terms.push(tantivy::Term::from_field_text(field, &token.text));
+    });
+    Ok(terms)
+}
+
+fn has_positions(schema: &Schema, field: Field) -> bool {
+    match schema.get_field_entry(field).field_type() {
+        FieldType::Str(text_options) => match text_options.get_indexing_options() {
+            Some(indexing) => indexing.index_option().has_positions(),
+            None => false,
+        },
+        _ => false,
+    }
+}
@@ -1,10 +1,14 @@
-use tantivy::collector::TopDocs;
+use fst_regex::Regex;
+use tantivy::collector::{Count, TopDocs};
 use tantivy::query::{Query, QueryParser};
-use tantivy::schema::{Field, Schema};
-use tantivy::{DocAddress, Index};
+use tantivy::schema::{Field, IndexRecordOption, Schema};
+use tantivy::{DocAddress, DocSet, Searcher, Term};
 
+use super::aggregation::{Aggregation, AggregationCollector, AggregationResult};
+use super::query::regex_query::SizeLimitedRegexQuery;
+use super::search_after::{Hit, Page, SearchAfterCollector};
 use super::tantex_error::TantexError;
-use TantexError::{FieldNotFound, InvalidQuery};
+use TantexError::{FieldNotFound, InvalidQuery, InvalidRegex};
 
 pub fn fetch_schema_fields(
     schema: &Schema,
@@ -28,20 +32,53 @@ pub fn fetch_field(schema: &Schema, field_name: &str) -> Result<Field, TantexErr
     }
 }
 
+// An empty list of field names means no projection: the whole document is
+// returned.
+pub fn fetch_projection_fields(
+    schema: &Schema,
+    field_strings: Vec<String>,
+) -> Result<Option<Vec<Field>>, TantexError> {
+    if field_strings.is_empty() {
+        return Ok(None);
+    }
+    let fields = fetch_schema_fields(schema, field_strings)?;
+    Ok(Some(fields))
+}
+
 pub fn parse_query(query_parser: &QueryParser, pattern: &str) -> Result<Box<Query>, TantexError> {
     match query_parser.parse_query(pattern) {
         Ok(q) => Ok(q),
-        Err(_) => Err(InvalidQuery(pattern.to_string())),
+        Err(e) => Err(InvalidQuery(pattern.to_string(), e)),
+    }
+}
+
+// The pattern is compiled once here against the caller's size limit so that
+// an invalid or oversized pattern is rejected before any search runs; the
+// query compiles it again, under the same limit, for each search.
+pub fn build_regex_query(
+    field: Field,
+    pattern: &str,
+    size_limit: usize,
+) -> Result<SizeLimitedRegexQuery, TantexError> {
+    match Regex::with_size_limit(size_limit, pattern) {
+        Ok(_) => Ok(SizeLimitedRegexQuery::new(
+            field,
+            pattern.to_string(),
+            size_limit,
+        )),
+        Err(e) => Err(InvalidRegex(pattern.to_string(), e)),
     }
 }
 
+// Takes the searcher rather than the index so that the caller loads the
+// documents from the same searcher: a commit in between would otherwise leave
+// the doc addresses pointing into a different list of segments.
 pub fn search_with_limit(
-    index: &Index,
+    searcher: &Searcher,
     query: &Query,
     limit: usize,
 ) -> Result<Vec<(f32, DocAddress)>, TantexError> {
     let collector = TopDocs::with_limit(limit);
-    let searcher = index.searcher();
     match searcher.search(query, &collector) {
         Ok(found) => Ok(found),
         Err(e1) => {
@@ -50,3 +87,74 @@ pub fn search_with_limit(
         }
     }
 }
+
+// Collects a page of hits and the aggregations over every matching document
+// in the same pass.
+pub fn search_with_aggregations(
+    searcher: &Searcher,
+    query: &Query,
+    page: Page,
+    aggregations: Vec<Aggregation>,
+) -> Result<(Vec<Hit>, Vec<AggregationResult>), TantexError> {
+    let collector = (
+        SearchAfterCollector::new(page),
+        AggregationCollector::new(aggregations),
+    );
+    match searcher.search(query, &collector) {
+        Ok(found) => Ok(found),
+        Err(e1) => {
+            let e2 = TantexError::SearchExecutionFailed(query.box_clone(), e1);
+            Err(e2)
+        }
+    }
+}
+
+// TopDocs keeps a heap of `offset + limit` hits per segment, so pages are
+// only served within the first MAX_RESULT_WINDOW hits.
+pub const MAX_RESULT_WINDOW: usize = 10_000;
+
+// Returns the hits in `offset..offset + limit` along with the total number of
+// matching documents.
+pub fn search_with_offset(
+    searcher: &Searcher,
+    query: &Query,
+    limit: usize,
+    offset: usize,
+) -> Result<(Vec<(f32, DocAddress)>, usize), TantexError> {
+    let result = if limit == 0 {
+        searcher
+            .search(query, &Count)
+            .map(|total| (Vec::new(), total))
+    } else {
+        let window = match offset.checked_add(limit) {
+            Some(window) if window <= MAX_RESULT_WINDOW => window,
+            _ => return Err(TantexError::ResultWindowTooLarge(MAX_RESULT_WINDOW)),
+        };
+        let collector = (TopDocs::with_limit(window), Count);
+        searcher.search(query, &collector)
+    };
+    match result {
+        Ok((found, total)) => Ok((found.into_iter().skip(offset).collect(), total)),
+        Err(e1) => {
+            let e2 = TantexError::SearchExecutionFailed(query.box_clone(), e1);
+            Err(e2)
+        }
+    }
+}
+
+// Resolves a term straight from the postings of each segment, skipping the
+// query and collector machinery. Returns the first live document.
+pub fn find_doc_address(searcher: &Searcher, term: &Term) -> Option<DocAddress> {
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let inverted_index = segment_reader.inverted_index(term.field());
+        if let Some(mut postings) = inverted_index.read_postings(term, IndexRecordOption::Basic) {
+            while postings.advance() {
+                let doc = postings.doc();
+                if !segment_reader.is_deleted(doc) {
+                    return Some(DocAddress(segment_ord as u32, doc));
+                }
+            }
+        }
+    }
+    None
+}
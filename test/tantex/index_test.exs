@@ -0,0 +1,369 @@
+defmodule Tantex.IndexTest do
+  use ExUnit.Case, async: true
+
+  alias Tantex.{Field, Index}
+
+  @docs [
+    %{id: 1, title: "red apple", body: "a sweet pear", seller: "acme", price: 3, tags: ["fruit"]},
+    %{id: 2, title: "green apple", body: "a sour pear", seller: "acme", price: 4, tags: []},
+    %{id: 3, title: "red car", body: "a fast red machine", seller: "motors", price: 20_000},
+    %{id: 4, title: "blue car", body: "a slow blue machine", seller: "motors", price: 15_000},
+    %{id: 5, title: "red hat", body: "a warm <red> hat", seller: "hats & co", price: 25}
+  ]
+
+  setup do
+    path = Path.join(System.tmp_dir!(), "tantex_#{System.unique_integer([:positive])}")
+    File.mkdir_p!(path)
+    on_exit(fn -> File.rm_rf!(path) end)
+
+    fields = [
+      Field.build("id", :u64),
+      Field.build("title", :text),
+      Field.build("body", :text),
+      Field.build("seller", :string),
+      Field.build("tags", :string, multi: true),
+      Field.build("price", :u64),
+      Field.build("blob", :bytes, fast: false)
+    ]
+
+    {:ok, index} = Tantex.open(path, fields, max_snapshots: 2)
+    {:ok, _} = Index.insert_documents(index, @docs)
+    {:ok, index: index}
+  end
+
+  defp ids(docs), do: docs |> Enum.map(& &1["id"]) |> Enum.sort()
+
+  defp leaves({:boolean, clauses}), do: Enum.flat_map(clauses, fn {_, query} -> leaves(query) end)
+  defp leaves(query), do: [query]
+
+  describe "documents" do
+    test "come back as maps with unwrapped single values", %{index: index} do
+      assert {:ok, %{"id" => 3, "title" => "red car", "seller" => "motors", "price" => 20_000}} =
+               Index.find_one(index, :id, 3)
+    end
+
+    test "keep multi-valued fields as lists", %{index: index} do
+      assert {:ok, %{"tags" => ["fruit"]}} = Index.find_one(index, :id, 1)
+      assert {:ok, doc} = Index.find_one(index, :id, 2)
+      refute Map.has_key?(doc, "tags")
+    end
+
+    test "reject lists for single-valued fields", %{index: index} do
+      assert {:error, {:invalid_document_value, _}} =
+               Index.insert_documents(index, [%{id: 6, seller: ["a", "b"]}])
+    end
+
+    test "reject keys that cannot name a field", %{index: index} do
+      assert {:error, {:invalid_document_value, message}} =
+               Index.insert_documents(index, [%{:id => 6, 1 => "one"}])
+
+      assert message =~ ~s(field_name: "1")
+    end
+
+    test "are projected to :return_fields", %{index: index} do
+      assert {:ok, %{"id" => 4} = doc} = Index.find_one(index, :id, 4, return_fields: [:id])
+      assert map_size(doc) == 1
+    end
+  end
+
+  describe "find_many/5" do
+    test "returns the documents matching the pattern", %{index: index} do
+      assert {:ok, docs} = Index.find_many(index, [:title], "red", 10)
+      assert ids(docs) == [1, 3, 5]
+    end
+
+    test "applies :exists and :missing filters", %{index: index} do
+      opts = [filters: [{:exists, :tags}]]
+      assert {:ok, docs} = Index.find_many(index, [:title], "apple", 10, opts)
+      assert ids(docs) == [1]
+
+      opts = [filters: [{:missing, :tags}]]
+      assert {:ok, docs} = Index.find_many(index, [:title], "apple", 10, opts)
+      assert ids(docs) == [2]
+    end
+
+    test "tells explicit zeros from missing integer values", %{index: index} do
+      docs = [%{id: 6, title: "red zero", price: 0}, %{id: 7, title: "red none"}]
+      {:ok, _} = Index.insert_documents(index, docs)
+
+      opts = [filters: [{:exists, :price}]]
+      assert {:ok, docs} = Index.find_many(index, [:title], "red", 10, opts)
+      assert ids(docs) == [1, 3, 5, 6]
+
+      opts = [filters: [{:missing, :price}]]
+      assert {:ok, docs} = Index.find_many(index, [:title], "red", 10, opts)
+      assert ids(docs) == [7]
+    end
+
+    test "rejects :exists on fields that are not indexed", %{index: index} do
+      opts = [filters: [{:exists, :blob}]]
+
+      assert {:error, {:field_not_indexed, "blob"}} =
+               Index.find_many(index, [:title], "red", 10, opts)
+    end
+
+    test "applies :in filters", %{index: index} do
+      opts = [filters: [{:in, :id, [1, 5]}]]
+      assert {:ok, docs} = Index.find_many(index, [:title], "red", 10, opts)
+      assert ids(docs) == [1, 5]
+    end
+
+    test "matches every term with :conjunction", %{index: index} do
+      assert {:ok, docs} = Index.find_many(index, [:title], "red car", 10, conjunction: true)
+      assert ids(docs) == [3]
+    end
+
+    test "ranks boosted fields first", %{index: index} do
+      opts = [boosts: [body: 10]]
+      assert {:ok, [first | _]} = Index.find_many(index, [:title, :body], "sweet car", 10, opts)
+      assert first["id"] == 1
+    end
+
+    test "returns snippets that honor filters", %{index: index} do
+      opts = [snippets: [:body], filters: [{:in, :id, [5]}], pre_tag: "<em>", post_tag: "</em>"]
+      assert {:ok, [%{document: %{"id" => 5}, snippets: %{"body" => snippet}}]} =
+               Index.find_many(index, [:body], "red", 10, opts)
+
+      assert snippet.html == "a warm &lt;<em>red</em>&gt; hat"
+    end
+
+    test "rejects options snippets cannot honor", %{index: index} do
+      opts = [snippets: [:body], aggregations: [price: {:stats, :price}]]
+      assert {:error, {:unsupported_option, _}} = Index.find_many(index, [:body], "red", 10, opts)
+    end
+
+    test "returns aggregations alongside the documents", %{index: index} do
+      opts = [aggregations: [price: {:stats, :price}]]
+      assert {:ok, docs, %{price: stats}} = Index.find_many(index, [:title], "apple", 1, opts)
+      assert length(docs) == 1
+      assert %{count: 2, min: 3.0, max: 4.0, sum: 7.0} = stats
+    end
+  end
+
+  describe "find_page/5" do
+    test "pages through every hit once", %{index: index} do
+      assert {:ok, first, cursor} = Index.find_page(index, [:body], "a", 2)
+      assert is_binary(cursor)
+      assert {:ok, second, cursor} = Index.find_page(index, [:body], "a", 2, after: cursor)
+      assert {:ok, third, nil} = Index.find_page(index, [:body], "a", 2, after: cursor)
+      assert ids(first ++ second ++ third) == [1, 2, 3, 4, 5]
+    end
+
+    test "rejects malformed and stale cursors", %{index: index} do
+      assert {:error, {:invalid_cursor, _}} =
+               Index.find_page(index, [:body], "a", 2, after: "not a cursor")
+
+      assert {:ok, _, cursor} = Index.find_page(index, [:body], "a", 2)
+      {:ok, _} = Index.insert_documents(index, [%{id: 6, body: "a new thing"}])
+
+      assert {:error, {:invalid_cursor, _}} =
+               Index.find_page(index, [:body], "a", 2, after: cursor)
+    end
+
+    test "keeps paging a snapshot across commits", %{index: index} do
+      assert {:ok, snapshot} = Index.pin_snapshot(index)
+      assert {:ok, first, cursor} = Index.find_page(index, [:body], "a", 3, snapshot: snapshot)
+      {:ok, _} = Index.insert_documents(index, [%{id: 6, body: "a new thing"}])
+
+      assert {:ok, rest, nil} =
+               Index.find_page(index, [:body], "a", 3, snapshot: snapshot, after: cursor)
+
+      assert ids(first ++ rest) == [1, 2, 3, 4, 5]
+      assert :ok = Index.release_snapshot(snapshot)
+
+      assert {:error, {:snapshot_released, _}} =
+               Index.find_page(index, [:body], "a", 3, snapshot: snapshot)
+    end
+  end
+
+  describe "snapshots" do
+    test "are capped at :max_snapshots", %{index: index} do
+      assert {:ok, first} = Index.pin_snapshot(index)
+      assert {:ok, _second} = Index.pin_snapshot(index)
+      assert {:error, {:too_many_snapshots, _}} = Index.pin_snapshot(index)
+      assert :ok = Index.release_snapshot(first)
+      assert {:ok, _} = Index.pin_snapshot(index)
+    end
+
+    test "are rejected by searches that cannot run on them", %{index: index} do
+      assert {:ok, snapshot} = Index.pin_snapshot(index)
+      opts = [snapshot: snapshot]
+      unsupported = {:error, {:unsupported_option, :snapshot}}
+
+      assert ^unsupported = Index.find_one(index, :id, 1, opts)
+      assert ^unsupported = Index.find_all(index, :seller, "acme", opts)
+      assert ^unsupported = Index.find_by_keys(index, :id, [1], opts)
+      assert ^unsupported = Index.find_by_regex(index, :seller, "ac.*", 10, opts)
+      assert ^unsupported = Index.explain(index, [:title], "red", {:id, 1}, opts)
+      assert ^unsupported = Index.aggregate(index, [:title], "red", [n: {:stats, :price}], opts)
+      assert ^unsupported = Index.find_grouped(index, [:title], "red", :seller, opts)
+    end
+  end
+
+  describe "lookups" do
+    test "find_all/4 pages through a term with the total count", %{index: index} do
+      assert {:ok, docs, 2} = Index.find_all(index, :seller, "acme", limit: 1)
+      assert length(docs) == 1
+      assert {:ok, [], 2} = Index.find_all(index, :seller, "acme", limit: 0)
+    end
+
+    test "find_all/4 rejects pages past the result window", %{index: index} do
+      assert {:error, {:result_window_too_large, _}} =
+               Index.find_all(index, :seller, "acme", limit: 1, offset: 10_000)
+    end
+
+    test "find_by_keys/4 marks missing keys", %{index: index} do
+      assert {:ok, %{1 => %{"id" => 1}, 9 => :not_found}} =
+               Index.find_by_keys(index, :id, [1, 9])
+    end
+
+    test "find_by_regex/5 matches terms of string fields", %{index: index} do
+      assert {:ok, docs} = Index.find_by_regex(index, :seller, "mo.*", 10)
+      assert ids(docs) == [3, 4]
+    end
+
+    test "find_by_regex/5 rejects patterns over :size_limit", %{index: index} do
+      assert {:error, {:invalid_regex, _}} =
+               Index.find_by_regex(index, :seller, "(mo|ac)[a-z]{2,30}", 10, size_limit: 100)
+    end
+  end
+
+  describe "explain/5 and parse_query/4" do
+    test "explains the score of a matching document", %{index: index} do
+      assert {:ok, %{matched: true, score: score, details: [_ | _]}} =
+               Index.explain(index, [:title], "red", {:id, 1})
+
+      assert score > 0
+      assert {:ok, %{matched: false}} = Index.explain(index, [:title], "red", {:id, 2})
+    end
+
+    test "shows how a pattern is parsed", %{index: index} do
+      assert {:ok, tree, _} = Index.parse_query(index, [:title], "red")
+      assert leaves(tree) == [{:term, "title", "red"}]
+
+      assert {:ok, tree, _} = Index.parse_query(index, [:title, :body], "red", boosts: [title: 3])
+      assert {:boost, 3.0, {:term, "title", "red"}} in leaves(tree)
+      assert {:term, "body", "red"} in leaves(tree)
+    end
+
+    test "reports why a pattern is invalid", %{index: index} do
+      assert {:error, {:invalid_query_format, {:field_does_not_exist, "nope"}}} =
+               Index.parse_query(index, [:title], "nope:red")
+
+      assert {:error, {:invalid_query_format, {:syntax_error, "(red"}}} =
+               Index.parse_query(index, [:title], "(red")
+    end
+  end
+
+  describe "aggregate/5" do
+    test "computes stats", %{index: index} do
+      aggregations = [stats: {:stats, :price}]
+      assert {:ok, %{stats: stats}} = Index.aggregate(index, [:body], "a", aggregations)
+      assert %{count: 5, min: 3.0, max: 20_000.0} = stats
+    end
+
+    test "rejects fields that are not fast", %{index: index} do
+      assert {:error, {:field_not_fast, _}} =
+               Index.aggregate(index, [:body], "a", stats: {:stats, :title})
+    end
+
+    test "computes histograms", %{index: index} do
+      aggregations = [histogram: {:histogram, :price, 10, min: 0, max: 29}]
+      assert {:ok, %{histogram: buckets}} = Index.aggregate(index, [:body], "a", aggregations)
+      assert Enum.map(buckets, &{&1.key, &1.doc_count}) == [{0.0, 2}, {10.0, 0}, {20.0, 1}]
+    end
+
+    test "computes top terms of string fields", %{index: index} do
+      aggregations = [sellers: {:terms, :seller, size: 1}]
+      assert {:ok, %{sellers: [bucket]}} = Index.aggregate(index, [:body], "a", aggregations)
+      assert %{key: key, doc_count: 2} = bucket
+      assert key in ["acme", "motors"]
+    end
+
+    test "rejects top terms of tokenized text fields", %{index: index} do
+      assert {:error, {:invalid_aggregation, _}} =
+               Index.aggregate(index, [:body], "a", titles: {:terms, :title})
+    end
+
+    test "computes named ranges", %{index: index} do
+      aggregations = [ranges: {:range, :price, [{"cheap", nil, 100}, {"dear", 100, nil}]}]
+      assert {:ok, %{ranges: ranges}} = Index.aggregate(index, [:body], "a", aggregations)
+      assert [%{name: "cheap", doc_count: 3}, %{name: "dear", doc_count: 2}] = ranges
+    end
+
+    test "estimates cardinality", %{index: index} do
+      aggregations = [distinct: {:cardinality, :price}]
+      assert {:ok, %{distinct: 5}} = Index.aggregate(index, [:body], "a", aggregations)
+    end
+
+    test "estimates percentiles keyed by the percents passed", %{index: index} do
+      aggregations = [percentiles: {:percentiles, :price, percents: [0, 100]}]
+      assert {:ok, results} = Index.aggregate(index, [:body], "a", aggregations)
+      assert results.percentiles == %{0 => 3.0, 100 => 20_000.0}
+
+      aggregations = [percentiles: {:percentiles, :price}]
+      assert {:ok, results} = Index.aggregate(index, [:body], "a", aggregations)
+      assert Map.has_key?(results.percentiles, 50)
+    end
+
+    test "rejects compressions out of bounds", %{index: index} do
+      for compression <- [0, 1_000_000] do
+        aggregations = [percentiles: {:percentiles, :price, compression: compression}]
+
+        assert {:error, {:invalid_aggregation, _}} =
+                 Index.aggregate(index, [:body], "a", aggregations)
+      end
+    end
+  end
+
+  describe "find_grouped/5" do
+    test "collapses hits on a string field", %{index: index} do
+      assert {:ok, groups} = Index.find_grouped(index, [:body], "a", :seller, per_group: 2)
+      by_key = Map.new(groups, &{&1.key, &1})
+      assert %{hit_count: 2, documents: [_, _]} = by_key["acme"]
+      assert %{hit_count: 2} = by_key["motors"]
+      assert %{hit_count: 1} = by_key["hats & co"]
+    end
+
+    test "rejects tokenized text fields", %{index: index} do
+      assert {:error, {:invalid_collapse_field, "title"}} =
+               Index.find_grouped(index, [:body], "a", :title)
+    end
+  end
+
+  describe "stream/5" do
+    test "sends chunks as they are acknowledged", %{index: index} do
+      assert {:ok, {id, _} = stream} = Index.stream(index, [:body], "a", self(), chunk_size: 2)
+
+      assert_receive {:tantex_stream, ^id, {:documents, [_, _] = first}}
+      refute_receive {:tantex_stream, ^id, _}, 100
+      :ok = Index.ack_stream(stream)
+      assert_receive {:tantex_stream, ^id, {:documents, [_, _] = second}}
+      :ok = Index.ack_stream(stream)
+      assert_receive {:tantex_stream, ^id, {:documents, [_] = third}}
+      assert_receive {:tantex_stream, ^id, :done}
+      assert ids(first ++ second ++ third) == [1, 2, 3, 4, 5]
+    end
+
+    test "stops without further messages once cancelled", %{index: index} do
+      assert {:ok, {id, _} = stream} = Index.stream(index, [:body], "a", self(), chunk_size: 1)
+
+      assert_receive {:tantex_stream, ^id, {:documents, [_]}}
+      :ok = Index.cancel_stream(stream)
+      refute_receive {:tantex_stream, ^id, _}, 100
+    end
+
+    test "ends with an error when chunks are not acknowledged", %{index: index} do
+      opts = [chunk_size: 1, timeout: 50]
+      assert {:ok, {id, _}} = Index.stream(index, [:body], "a", self(), opts)
+
+      assert_receive {:tantex_stream, ^id, {:documents, [_]}}
+      assert_receive {:tantex_stream, ^id, {:error, {:stream_timed_out, _}}}, 1_000
+    end
+
+    test "returns query errors from the call", %{index: index} do
+      assert {:error, {:invalid_query_format, _}} =
+               Index.stream(index, [:body], "nope:a", self())
+    end
+  end
+end